@@ -0,0 +1,106 @@
+use std::path::{Component, Path, PathBuf};
+use image::{self, DynamicImage};
+use std::env;
+
+// System-wide asset directory, searched after the user directories
+const SYSTEM_DIR: &str = "/usr/share/leechbar";
+
+// Find asset files in all asset directories
+#[derive(Clone)]
+pub struct Assets {
+    dirs: Vec<PathBuf>,
+}
+
+impl Assets {
+    // Search the configured directory first, then the data directories
+    pub fn new(configured: Option<PathBuf>) -> Self {
+        let mut dirs: Vec<PathBuf> = configured.into_iter().collect();
+
+        let data_home = env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::home_dir().map(|home| home.join(".local/share")));
+        if let Some(data_home) = data_home {
+            dirs.push(data_home.join("leechbar"));
+        }
+
+        dirs.push(PathBuf::from(SYSTEM_DIR));
+
+        Self {
+            dirs: dirs.iter().map(|dir| absolute(dir)).collect(),
+        }
+    }
+
+    // All directories which are searched for assets, in order
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    // Path of the first file matching the asset name
+    pub fn resolve<T: AsRef<Path>>(&self, name: T) -> Option<PathBuf> {
+        self.dirs
+            .iter()
+            .map(|dir| dir.join(name.as_ref()))
+            .find(|path| path.is_file())
+    }
+
+    // Name of the asset stored at an absolute path, if it is inside an asset directory
+    pub fn name(&self, path: &Path) -> Option<PathBuf> {
+        self.dirs
+            .iter()
+            .filter_map(|dir| path.strip_prefix(dir).ok())
+            .next()
+            .map(Path::to_path_buf)
+    }
+
+    // Load an image, falling back to the images embedded into the binary
+    pub fn image<T: AsRef<Path>>(&self, name: T) -> Option<DynamicImage> {
+        let name = name.as_ref();
+
+        if let Some(path) = self.resolve(name) {
+            match image::open(&path) {
+                Ok(img) => return Some(img),
+                Err(e) => warn!("Unable to load image {:?}: {}", path, e),
+            }
+        }
+
+        embedded(name).and_then(|bytes| image::load_from_memory(bytes).ok())
+    }
+}
+
+// Make a path absolute without requiring it to exist
+pub fn absolute(path: &Path) -> PathBuf {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().unwrap_or_default().join(path)
+    };
+
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+// Default images compiled into the binary
+#[cfg(feature = "embed-assets")]
+fn embedded(name: &Path) -> Option<&'static [u8]> {
+    let bytes: &'static [u8] = match name.to_str()? {
+        "bg.png" => include_bytes!("../images/bg.png"),
+        "bg_sec.png" => include_bytes!("../images/bg_sec.png"),
+        "ws/empty.png" => include_bytes!("../images/ws/empty.png"),
+        "ws/empty_sec.png" => include_bytes!("../images/ws/empty_sec.png"),
+        "ws/firefox.png" => include_bytes!("../images/ws/firefox.png"),
+        "ws/firefox_sec.png" => include_bytes!("../images/ws/firefox_sec.png"),
+        "ws/mixed.png" => include_bytes!("../images/ws/mixed.png"),
+        "ws/mixed_sec.png" => include_bytes!("../images/ws/mixed_sec.png"),
+        "ws/termite.png" => include_bytes!("../images/ws/termite.png"),
+        "ws/termite_sec.png" => include_bytes!("../images/ws/termite_sec.png"),
+        _ => return None,
+    };
+
+    Some(bytes)
+}
+
+#[cfg(not(feature = "embed-assets"))]
+fn embedded(_: &Path) -> Option<&'static [u8]> {
+    None
+}
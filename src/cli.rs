@@ -0,0 +1,99 @@
+use clap::{App, Arg, ArgMatches};
+use std::path::PathBuf;
+
+pub struct Options {
+    pub output: Option<String>,
+    pub ws_offset: i32,
+    pub ws_count: Option<i32>,
+    pub config: Option<PathBuf>,
+    pub assets_dir: Option<PathBuf>,
+    pub list_outputs: bool,
+}
+
+// Parse the command line, exits with usage information on invalid arguments
+pub fn parse() -> Options {
+    let matches = app().get_matches();
+    Options::from_matches(&matches)
+}
+
+impl Options {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        // All numbers have been checked by the validators already
+        let ws_offset = matches
+            .value_of("workspace-offset")
+            .map_or(1, |offset| offset.parse().unwrap());
+        let ws_count = matches
+            .value_of("workspace-count")
+            .map(|count| count.parse().unwrap());
+
+        Self {
+            ws_offset,
+            ws_count,
+            output: matches.value_of("output").map(String::from),
+            config: matches.value_of("config").map(PathBuf::from),
+            assets_dir: matches.value_of("assets-dir").map(PathBuf::from),
+            list_outputs: matches.is_present("list-outputs"),
+        }
+    }
+}
+
+fn app() -> App<'static, 'static> {
+    App::new("leechbar")
+        .version(crate_version!())
+        .about("Status bar for i3 built on leechbar")
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Output the bar is displayed on, see --list-outputs")
+                .required_unless("list-outputs"),
+        )
+        .arg(
+            Arg::with_name("workspace-offset")
+                .long("workspace-offset")
+                .value_name("N")
+                .help("Number of the first workspace on this output [default: 1]")
+                .allow_hyphen_values(true)
+                .validator(validate_number),
+        )
+        .arg(
+            Arg::with_name("workspace-count")
+                .long("workspace-count")
+                .value_name("N")
+                .help("Number of workspace buttons, overrides the config")
+                .validator(validate_count),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("Config file [default: $XDG_CONFIG_HOME/leechbar/config.toml]"),
+        )
+        .arg(
+            Arg::with_name("assets-dir")
+                .long("assets-dir")
+                .value_name("DIR")
+                .help("Directory searched for images before the data directories"),
+        )
+        .arg(
+            Arg::with_name("list-outputs")
+                .long("list-outputs")
+                .help("Print all active outputs and exit"),
+        )
+}
+
+fn validate_number(value: String) -> Result<(), String> {
+    value
+        .parse::<i32>()
+        .map(|_| ())
+        .map_err(|_| format!("'{}' is not a number", value))
+}
+
+fn validate_count(value: String) -> Result<(), String> {
+    match value.parse::<i32>() {
+        Ok(count) if count > 0 => Ok(()),
+        _ => Err(format!("'{}' is not a positive number", value)),
+    }
+}
@@ -0,0 +1,598 @@
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use leechbar::Color;
+use std::fs::File;
+use std::fmt;
+use std::env;
+use toml;
+
+// Default bar settings, used for every key missing from the config file
+const BG: &str = "bg.png";
+const FONT: &str = "Fira Sans 12";
+const NAME: &str = "LeechBar";
+const HEIGHT: u16 = 32;
+const FOREGROUND: &str = "#9e9e9e";
+
+// Everything that can go wrong while loading the config
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid { key: String, message: String },
+}
+
+impl ConfigError {
+    pub fn invalid<K: Into<String>, M: Into<String>>(key: K, message: M) -> Self {
+        ConfigError::Invalid {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref path, ref e) => {
+                write!(f, "Unable to read config {:?}: {}", path, e)
+            }
+            ConfigError::Parse(ref path, ref e) => {
+                write!(f, "Unable to parse config {:?}: {}", path, e)
+            }
+            ConfigError::Invalid {
+                ref key,
+                ref message,
+            } => write!(f, "Invalid config value for key `{}`: {}", key, message),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bar: BarConfig,
+    pub components: Vec<ComponentConfig>,
+}
+
+impl Config {
+    // Load the config from `path`, falling back to the default location
+    //
+    // If no path has been specified and there is no config file at the default
+    // location, the default config is used.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(ref path) if path.exists() => path.clone(),
+                _ => {
+                    info!("No config file found, using defaults");
+                    return Ok(Self::default().with_default_components());
+                }
+            },
+        };
+
+        let mut content = String::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| ConfigError::Io(path.clone(), e))?;
+
+        let mut config: Config =
+            toml::from_str(&content).map_err(|e| ConfigError::Parse(path.clone(), e))?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        let config = config.with_default_components();
+        config.validate()?;
+
+        info!("Loaded config {:?}", path);
+        Ok(config)
+    }
+
+    // Relative paths are relative to the config file, not to the directory the bar was started in
+    fn resolve_paths(&mut self, dir: &Path) {
+        let resolve = |path: &mut PathBuf| if path.is_relative() {
+            *path = dir.join(&path);
+        };
+
+        if let Some(ref mut assets_dir) = self.bar.assets_dir {
+            resolve(assets_dir);
+        }
+
+        for component in &mut self.components {
+            if let ComponentConfig::Workspaces(ref mut ws) = *component {
+                if let Some(ref mut rules) = ws.rules {
+                    resolve(rules);
+                }
+            }
+        }
+    }
+
+    // Use the original bar layout when no components have been specified
+    fn with_default_components(mut self) -> Self {
+        if self.components.is_empty() {
+            self.components = vec![
+                ComponentConfig::Workspaces(WorkspacesConfig::default()),
+                ComponentConfig::Time(TimeConfig::default()),
+                ComponentConfig::Volume(VolumeConfig::default()),
+            ];
+        }
+        self
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        self.bar.validate()?;
+
+        for (i, component) in self.components.iter().enumerate() {
+            let key = format!("components[{}]", i);
+            match *component {
+                ComponentConfig::Workspaces(ref ws) => ws.validate(&key)?,
+                ComponentConfig::Time(ref time) => time.validate(&key)?,
+                ComponentConfig::Volume(ref vol) => vol.validate(&key)?,
+                ComponentConfig::Microphone(ref mic) => mic.validate(&key)?,
+                ComponentConfig::Recording(ref rec) => rec.validate(&key)?,
+                ComponentConfig::Mode(ref mode) => mode.validate(&key)?,
+                ComponentConfig::WindowTitle(_) => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BarConfig {
+    pub assets_dir: Option<PathBuf>,
+    pub background: String,
+    pub font: String,
+    pub name: String,
+    pub height: u16,
+    pub foreground: String,
+    pub text_yoffset: i16,
+}
+
+impl Default for BarConfig {
+    fn default() -> Self {
+        Self {
+            assets_dir: None,
+            background: BG.into(),
+            font: FONT.into(),
+            name: NAME.into(),
+            height: HEIGHT,
+            foreground: FOREGROUND.into(),
+            text_yoffset: -1,
+        }
+    }
+}
+
+impl BarConfig {
+    // The foreground color, only valid after the config has been validated
+    pub fn foreground_color(&self) -> Color {
+        parse_color(&self.foreground).unwrap()
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.height == 0 {
+            return Err(ConfigError::invalid("bar.height", "must be greater than 0"));
+        }
+
+        validate_color(Some(&self.foreground), "bar.foreground")
+    }
+}
+
+// All available components, selected by the `type` key
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ComponentConfig {
+    Workspaces(WorkspacesConfig),
+    Time(TimeConfig),
+    Volume(VolumeConfig),
+    Microphone(MicrophoneConfig),
+    Recording(RecordingConfig),
+    Mode(ModeConfig),
+    WindowTitle(WindowTitleConfig),
+}
+
+// How the workspace buttons are chosen
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceMode {
+    // `count` buttons for the workspaces `offset + n * step`
+    Fixed,
+    // Up to `count` buttons for the workspaces i3 reports on this output
+    Dynamic,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspacesConfig {
+    pub mode: WorkspaceMode,
+    pub count: i32,
+    pub step: i32,
+    pub width: u16,
+    pub actions: WorkspaceActions,
+    pub rules: Option<PathBuf>,
+    // Scrolling past the last workspace continues at the first one
+    pub wrap: bool,
+    // Scrolling ignores workspaces without windows
+    pub skip_empty: bool,
+    pub badge: Badge,
+    // Most applications shown by the `icons` badge
+    pub max_icons: usize,
+    // Color of the `count` badge, defaults to the bar foreground
+    pub badge_color: Option<String>,
+}
+
+impl Default for WorkspacesConfig {
+    fn default() -> Self {
+        Self {
+            mode: WorkspaceMode::Fixed,
+            count: 5,
+            step: 3,
+            width: 60,
+            actions: WorkspaceActions::default(),
+            rules: None,
+            wrap: true,
+            skip_empty: false,
+            badge: Badge::Count,
+            max_icons: 3,
+            badge_color: None,
+        }
+    }
+}
+
+impl WorkspacesConfig {
+    fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        if self.count <= 0 {
+            return Err(ConfigError::invalid(
+                format!("{}.count", key),
+                "must be greater than 0",
+            ));
+        }
+
+        if self.step <= 0 {
+            return Err(ConfigError::invalid(
+                format!("{}.step", key),
+                "must be greater than 0",
+            ));
+        }
+
+        if self.max_icons == 0 {
+            return Err(ConfigError::invalid(
+                format!("{}.max_icons", key),
+                "must be greater than 0",
+            ));
+        }
+
+        validate_color(self.badge_color.as_ref(), &format!("{}.badge_color", key))?;
+
+        self.actions.validate(&format!("{}.actions", key))
+    }
+}
+
+// How the number of windows on a workspace is shown
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Badge {
+    // Only the icon of the applications, `mixed` for several applications
+    None,
+    // Number of windows on workspaces with more than one window
+    Count,
+    // Small icons of up to `max_icons` applications instead of `mixed`
+    Icons,
+}
+
+// Actions run when clicking on a workspace button
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspaceActions {
+    // Also used for switching workspaces by scrolling, defaults to an i3 `workspace` command
+    pub left: Option<Action>,
+    pub middle: Option<Action>,
+    pub right: Option<Action>,
+}
+
+impl Default for WorkspaceActions {
+    fn default() -> Self {
+        Self {
+            left: None,
+            middle: Some(Action {
+                i3: Some("move container to workspace {workspace}".into()),
+                shell: None,
+            }),
+            right: None,
+        }
+    }
+}
+
+impl WorkspaceActions {
+    fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        let actions = [
+            ("left", &self.left),
+            ("middle", &self.middle),
+            ("right", &self.right),
+        ];
+
+        for &(button, action) in &actions {
+            if let Some(ref action) = *action {
+                if action.i3.is_some() == action.shell.is_some() {
+                    return Err(ConfigError::invalid(
+                        format!("{}.{}", key, button),
+                        "needs exactly one of `i3` or `shell`",
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Command template, `{workspace}` is replaced with the workspace name and
+// `{name}` with the icon name of its windows, both are quoted already
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Action {
+    pub i3: Option<String>,
+    pub shell: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeConfig {
+    pub format: String,
+    pub interval: u64,
+    pub width: u16,
+}
+
+impl Default for TimeConfig {
+    fn default() -> Self {
+        Self {
+            format: "%H:%M".into(),
+            interval: 15,
+            width: 100,
+        }
+    }
+}
+
+impl TimeConfig {
+    fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        if self.interval == 0 {
+            return Err(ConfigError::invalid(
+                format!("{}.interval", key),
+                "must be greater than 0",
+            ));
+        }
+
+        if let Err(e) = ::time::strftime(&self.format, &::time::now()) {
+            return Err(ConfigError::invalid(format!("{}.format", key), e.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VolumeConfig {
+    pub x: i16,
+    pub y: i16,
+    pub width: i16,
+    pub height: i16,
+}
+
+impl Default for VolumeConfig {
+    fn default() -> Self {
+        Self {
+            x: 20,
+            y: 10,
+            width: 150,
+            height: 10,
+        }
+    }
+}
+
+impl VolumeConfig {
+    fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        if self.x < 0 || self.y < 0 {
+            return Err(ConfigError::invalid(
+                format!("{}.{}", key, if self.x < 0 { "x" } else { "y" }),
+                "must not be negative",
+            ));
+        }
+
+        if self.width <= 0 || self.height <= 0 {
+            return Err(ConfigError::invalid(
+                format!("{}.{}", key, if self.width <= 0 { "width" } else { "height" }),
+                "must be greater than 0",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MicrophoneConfig {
+    pub width: u16,
+    pub background: String,
+    // Volume change in percent for every scroll step
+    pub step: usize,
+    // `{volume}` is replaced with the volume in percent
+    pub format: String,
+    pub muted_format: String,
+}
+
+impl Default for MicrophoneConfig {
+    fn default() -> Self {
+        Self {
+            width: 75,
+            background: "bg_sec.png".into(),
+            step: 5,
+            format: "mic {volume}".into(),
+            muted_format: "mic off".into(),
+        }
+    }
+}
+
+impl MicrophoneConfig {
+    fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        if self.step == 0 || self.step > 100 {
+            return Err(ConfigError::invalid(
+                format!("{}.step", key),
+                "must be between 1 and 100",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RecordingConfig {
+    pub width: u16,
+    // Width while the recording applications are listed
+    pub list_width: u16,
+    pub label: String,
+    // Shown in the list while nothing is recording
+    pub idle_list: String,
+    pub background: String,
+    pub active_background: String,
+    // Color of the text while an application is recording
+    pub color: String,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            width: 50,
+            list_width: 250,
+            label: "rec".into(),
+            idle_list: "not recording".into(),
+            background: "bg_sec.png".into(),
+            active_background: "bg_sec.png".into(),
+            color: "#d33a3a".into(),
+        }
+    }
+}
+
+impl RecordingConfig {
+    fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        validate_color(Some(&self.color), &format!("{}.color", key))
+    }
+}
+
+// Directory containing all config files, `$XDG_CONFIG_HOME/leechbar`
+pub fn config_dir() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".config")));
+
+    config_home.map(|dir| dir.join("leechbar"))
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModeConfig {
+    pub width: u16,
+    pub background: String,
+    pub color: Option<String>,
+    // Style overrides by mode name
+    pub modes: HashMap<String, ModeStyle>,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModeStyle {
+    pub background: Option<String>,
+    pub color: Option<String>,
+}
+
+impl Default for ModeConfig {
+    fn default() -> Self {
+        Self {
+            width: 100,
+            background: "bg_sec.png".into(),
+            color: None,
+            modes: HashMap::new(),
+        }
+    }
+}
+
+impl ModeConfig {
+    fn validate(&self, key: &str) -> Result<(), ConfigError> {
+        validate_color(self.color.as_ref(), &format!("{}.color", key))?;
+
+        for (name, style) in &self.modes {
+            let key = format!("{}.modes.{}.color", key, name);
+            validate_color(style.color.as_ref(), &key)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Text shown by the window title component
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleSource {
+    Title,
+    // X11 window class or Wayland app id
+    Class,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowTitleConfig {
+    pub width: u16,
+    pub show: TitleSource,
+    pub background: String,
+}
+
+impl Default for WindowTitleConfig {
+    fn default() -> Self {
+        Self {
+            width: 300,
+            show: TitleSource::Title,
+            background: "bg_sec.png".into(),
+        }
+    }
+}
+
+fn validate_color(color: Option<&String>, key: &str) -> Result<(), ConfigError> {
+    match color {
+        Some(color) if parse_color(color).is_none() => Err(ConfigError::invalid(
+            key,
+            format!("'{}' is not a '#rrggbb' or '#rrggbbaa' color", color),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Location of the config file, `$XDG_CONFIG_HOME/leechbar/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+// Color of the text of muted devices and idle indicators
+pub fn inactive_color() -> Color {
+    Color::new(94, 94, 94, 255)
+}
+
+// Parse a color in the `#rrggbb` or `#rrggbbaa` format
+pub fn parse_color(color: &str) -> Option<Color> {
+    if !color.starts_with('#') || (color.len() != 7 && color.len() != 9) {
+        return None;
+    }
+
+    let mut channels = [255u8; 4];
+    for (i, channel) in channels.iter_mut().enumerate().take((color.len() - 1) / 2) {
+        let hex = color.get(1 + i * 2..3 + i * 2)?;
+        *channel = u8::from_str_radix(hex, 16).ok()?;
+    }
+
+    Some(Color::new(channels[0], channels[1], channels[2], channels[3]))
+}
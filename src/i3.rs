@@ -1,269 +1,1216 @@
-use i3ipc::event::inner::WindowChange::{Close, Move, New};
-use i3ipc::event::inner::WorkspaceChange::{Empty, Focus};
-use i3ipc::event::Event::{WindowEvent, WorkspaceEvent};
+use i3ipc::event::inner::WindowChange::{self, Close, Floating, FullscreenMode, Move, New, Title};
+use i3ipc::event::inner::WorkspaceChange::Focus;
+use i3ipc::event::Event::{self, ModeEvent, OutputEvent, ShutdownEvent, WindowEvent,
+                          WorkspaceEvent};
 use std::collections::HashMap;
-use std::sync::mpsc::Sender;
+use std::iter::Chain;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use i3ipc::reply::Node;
+use i3ipc::{MessageError, Subscription};
+use std::fmt::Display;
+use i3ipc::reply::{self, Node};
+use icons::{self, Window};
+use std::path::Path;
+use serde_json;
 use std::thread;
+use std::slice;
+use std::env;
 use i3ipc;
+use ipc;
 
+// Delays between attempts to reconnect to i3
+const MIN_BACKOFF_MS: u64 = 100;
+const MAX_BACKOFF_MS: u64 = 10_000;
+
+// Name of the mode i3 is in when no mode is active
+pub const DEFAULT_MODE: &str = "default";
+
+// Display state of a workspace on the bar's output
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WorkspaceStatus {
+    Focused,
+    // Visible on this output, while another output has focus
+    Visible,
+    Hidden,
+}
+
+impl Default for WorkspaceStatus {
+    fn default() -> Self {
+        WorkspaceStatus::Hidden
+    }
+}
+
+impl WorkspaceStatus {
+    // Workspaces on other outputs are always hidden
+    fn of(workspace: &WorkspaceInfo, output: &str) -> Self {
+        if workspace.output != output {
+            WorkspaceStatus::Hidden
+        } else if workspace.focused {
+            WorkspaceStatus::Focused
+        } else if workspace.visible {
+            WorkspaceStatus::Visible
+        } else {
+            WorkspaceStatus::Hidden
+        }
+    }
+}
+
+// Number of windows of one application on a workspace
+#[derive(Clone, PartialEq, Debug)]
+pub struct AppCount {
+    pub name: String,
+    pub windows: usize,
+}
+
+#[derive(Default, PartialEq, Debug)]
 pub struct I3Change {
-    pub state: Option<bool>,
+    pub state: Option<WorkspaceStatus>,
     pub title: Option<String>,
+    // Applications on the workspace, sorted by name
+    pub apps: Option<Vec<AppCount>>,
+    pub urgent: Option<bool>,
+    // Workspace shown by a dynamic button, `Some(None)` hides the button
+    pub workspace: Option<Option<String>>,
+    // Whether i3 is available, the state is outdated while it's not
+    pub connected: Option<bool>,
 }
 
 impl I3Change {
-    fn new(state: Option<bool>, title: Option<String>) -> Self {
-        Self { state, title }
+    fn apps(apps: Vec<AppCount>) -> Self {
+        Self {
+            title: Some(title(&apps)),
+            apps: Some(apps),
+            ..Self::default()
+        }
+    }
+
+    fn assign(
+        workspace: Option<String>,
+        state: WorkspaceStatus,
+        apps: Vec<AppCount>,
+        urgent: bool,
+    ) -> Self {
+        Self {
+            state: Some(state),
+            urgent: Some(urgent),
+            workspace: Some(workspace),
+            ..Self::apps(apps)
+        }
     }
 }
 
-pub struct I3Listener {
-    old_state: bool,
-    old_title: String,
+// The focused window, sent to window title components
+#[derive(Clone, PartialEq)]
+pub struct FocusedWindow {
+    pub title: String,
+    // X11 window class or Wayland app id
+    pub class: Option<String>,
+}
+
+impl FocusedWindow {
+    // Window of an event, the class is only known from the tree
+    fn new(tree: &Tree, node: &Node) -> Self {
+        Self {
+            class: tree.class(node.id).map(str::to_owned),
+            title: node.name.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// Workspace button and the state it has been sent last
+struct I3Listener {
+    old_state: WorkspaceStatus,
+    old_apps: Vec<AppCount>,
+    old_urgent: bool,
+    old_connected: bool,
     sender: Sender<I3Change>,
 }
 
 impl I3Listener {
+    // Buttons start out hidden and empty
     fn new(sender: Sender<I3Change>) -> Self {
         Self {
             sender,
-            old_state: false,
-            old_title: String::new(),
+            old_state: WorkspaceStatus::Hidden,
+            old_apps: Vec::new(),
+            old_urgent: false,
+            old_connected: true,
+        }
+    }
+
+    // Send everything about the workspace which changed since the last update
+    fn update(&mut self, state: &State, workspace: &str, output: &str) {
+        let (status, apps, urgent) = state.summary(workspace, output);
+        let mut change = I3Change::default();
+        let mut changed = false;
+
+        if status != self.old_state {
+            self.old_state = status;
+            change.state = Some(status);
+            changed = true;
+        }
+
+        if apps != self.old_apps {
+            change.title = Some(title(&apps));
+            change.apps = Some(apps.clone());
+            self.old_apps = apps;
+            changed = true;
+        }
+
+        if urgent != self.old_urgent {
+            self.old_urgent = urgent;
+            change.urgent = Some(urgent);
+            changed = true;
+        }
+
+        if state.connected != self.old_connected {
+            self.old_connected = state.connected;
+            change.connected = Some(state.connected);
+            changed = true;
+        }
+
+        if changed {
+            let _ = self.sender.send(change);
+        }
+    }
+}
+
+// Connection for running i3 commands, shared by all components
+#[derive(Clone)]
+pub struct I3Commander {
+    conn: Arc<Mutex<Option<i3ipc::I3Connection>>>,
+}
+
+impl I3Commander {
+    fn new() -> Self {
+        Self {
+            conn: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Run an i3 command, reconnecting once if the connection has been lost
+    pub fn run(&self, command: &str) {
+        let mut lock = self.conn.lock().unwrap();
+
+        for _ in 0..2 {
+            if lock.is_none() {
+                match i3ipc::I3Connection::connect() {
+                    Ok(conn) => *lock = Some(conn),
+                    Err(e) => {
+                        error!("Unable to connect to i3: {}", e);
+                        return;
+                    }
+                }
+            }
+
+            let result = lock.as_mut().unwrap().run_command(command);
+            match result {
+                Ok(reply) => {
+                    for outcome in reply.outcomes.into_iter().filter(|o| !o.success) {
+                        let error = outcome.error.unwrap_or_default();
+                        warn!("i3 command '{}' failed: {}", command, error);
+                    }
+                    return;
+                }
+                Err(e) => {
+                    warn!("Lost i3 command connection: {}", e);
+                    *lock = None;
+                }
+            }
+        }
+    }
+}
+
+// Buttons of an output which follow the workspaces i3 reports for it
+struct DynamicWorkspaces {
+    output: String,
+    slots: Vec<(Option<String>, Sender<I3Change>)>,
+}
+
+// Workspace as reported by i3
+#[derive(Clone)]
+struct WorkspaceInfo {
+    num: i32,
+    name: String,
+    output: String,
+    focused: bool,
+    visible: bool,
+    urgent: bool,
+}
+
+impl<'a> From<&'a reply::Workspace> for WorkspaceInfo {
+    fn from(workspace: &reply::Workspace) -> Self {
+        Self {
+            num: workspace.num,
+            name: workspace.name.clone(),
+            output: workspace.output.clone(),
+            focused: workspace.focused,
+            visible: workspace.visible,
+            urgent: workspace.urgent,
+        }
+    }
+}
+
+// Everything the bar knows about i3
+#[derive(Clone)]
+struct State {
+    // Numbered workspaces first, named workspaces afterwards
+    workspaces: Vec<WorkspaceInfo>,
+    // Applications on each workspace with windows
+    apps: HashMap<String, Vec<AppCount>>,
+    // Names of all active outputs
+    outputs: Vec<String>,
+    mode: String,
+    focused: Option<FocusedWindow>,
+    connected: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            workspaces: Vec::new(),
+            apps: HashMap::new(),
+            outputs: Vec::new(),
+            mode: DEFAULT_MODE.into(),
+            focused: None,
+            connected: false,
+        }
+    }
+}
+
+impl State {
+    // Status, applications and urgency of a workspace, workspaces i3 doesn't know are empty
+    fn summary(&self, name: &str, output: &str) -> (WorkspaceStatus, Vec<AppCount>, bool) {
+        let apps = self.apps.get(name).cloned().unwrap_or_default();
+        match self.workspaces.iter().find(|ws| ws.name == name) {
+            Some(ws) => (WorkspaceStatus::of(ws, output), apps, ws.urgent),
+            None => (WorkspaceStatus::Hidden, apps, false),
+        }
+    }
+
+    fn update_workspaces(&mut self, conn: &mut i3ipc::I3Connection) -> Result<(), MessageError> {
+        let mut workspaces: Vec<WorkspaceInfo> = conn.get_workspaces()?
+            .workspaces
+            .iter()
+            .map(WorkspaceInfo::from)
+            .collect();
+        workspaces.sort_by_key(|ws| (ws.num < 0, ws.num, ws.name.clone()));
+
+        self.workspaces = workspaces;
+        Ok(())
+    }
+
+    fn update_outputs(&mut self, conn: &mut i3ipc::I3Connection) -> Result<(), MessageError> {
+        self.outputs = conn.get_outputs()?
+            .outputs
+            .into_iter()
+            .filter(|output| output.active)
+            .map(|output| output.name)
+            .collect();
+        Ok(())
+    }
+
+    // Refresh everything, used after (re)connecting because events might have been missed
+    fn fetch(
+        conn: &mut i3ipc::I3Connection,
+        socket: &Path,
+    ) -> Result<(Self, Tree), MessageError> {
+        let mut state = State::default();
+        state.update_workspaces(conn)?;
+        state.update_outputs(conn)?;
+
+        let tree = Tree::fetch(socket)?;
+        state.apps = tree.workspace_apps();
+        state.focused = tree.focused_window();
+        state.connected = true;
+
+        Ok((state, tree))
+    }
+}
+
+// The state and everyone who wants to know about it
+//
+// This is the only lock of the i3 module, it is never held while talking to i3.
+struct Shared {
+    output: String,
+    state: State,
+    senders: HashMap<String, I3Listener>,
+    dynamic: Vec<DynamicWorkspaces>,
+    modes: Vec<Sender<String>>,
+    focused: Vec<Sender<Option<FocusedWindow>>>,
+}
+
+impl Shared {
+    // Replace the state and send all changes to the components
+    fn publish(&mut self, state: State) {
+        let output_missing = !state.outputs.contains(&self.output);
+        if state.connected && state.outputs != self.state.outputs && output_missing {
+            warn!("Output '{}' is not active", self.output);
+        }
+
+        if state.mode != self.state.mode {
+            // Drop senders of components which have been removed
+            let mode = &state.mode;
+            self.modes.retain(|sender| sender.send(mode.clone()).is_ok());
+        }
+
+        if state.focused != self.state.focused {
+            let focused = &state.focused;
+            self.focused.retain(|sender| sender.send(focused.clone()).is_ok());
+        }
+
+        self.state = state;
+        self.assign_dynamic();
+
+        for (workspace, listener) in &mut self.senders {
+            listener.update(&self.state, workspace, &self.output);
+        }
+    }
+
+    // Assign the workspaces of each output to its dynamic buttons
+    fn assign_dynamic(&mut self) {
+        let state = &self.state;
+        let senders = &mut self.senders;
+        for group in &mut self.dynamic {
+            let names: Vec<Option<String>> = {
+                let mut output_workspaces =
+                    state.workspaces.iter().filter(|ws| ws.output == group.output);
+                group
+                    .slots
+                    .iter()
+                    .map(|_| output_workspaces.next().map(|ws| ws.name.clone()))
+                    .collect()
+            };
+
+            // Unregister all changed buttons first, their workspace might move to another button
+            for (&(ref assigned, _), name) in group.slots.iter().zip(&names) {
+                if let Some(ref old_name) = *assigned {
+                    if Some(old_name) != name.as_ref() {
+                        senders.remove(old_name);
+                    }
+                }
+            }
+
+            for (&mut (ref mut assigned, ref sender), name) in group.slots.iter_mut().zip(names) {
+                if *assigned == name {
+                    continue;
+                }
+
+                // The button might have shown another workspace, so send everything
+                let (status, apps, urgent) = match name {
+                    Some(ref name) => state.summary(name, &group.output),
+                    None => (WorkspaceStatus::Hidden, Vec::new(), false),
+                };
+                let _ = sender.send(I3Change {
+                    connected: Some(state.connected),
+                    ..I3Change::assign(name.clone(), status, apps.clone(), urgent)
+                });
+
+                if let Some(ref name) = name {
+                    let mut listener = I3Listener::new(sender.clone());
+                    listener.old_state = status;
+                    listener.old_apps = apps;
+                    listener.old_urgent = urgent;
+                    listener.old_connected = state.connected;
+                    senders.insert(name.clone(), listener);
+                }
+                *assigned = name;
+            }
         }
     }
 }
 
+// Input of the event thread
+enum Message {
+    // Event of the connection with this number
+    Event(usize, Event),
+    // The connection with this number has been closed
+    Closed(usize),
+    // The icon rules changed, so the window names have to be chosen again
+    Refresh,
+}
+
 pub struct I3 {
-    senders: Arc<Mutex<HashMap<String, I3Listener>>>,
-    tree: Arc<Mutex<Node>>,
+    shared: Arc<Mutex<Shared>>,
+    commander: I3Commander,
+    messages: Sender<Message>,
 }
 
 impl I3 {
-    pub fn new() -> Self {
-        let mut conn = i3ipc::I3Connection::connect().unwrap();
+    // Track the workspaces of `output`
+    pub fn new(output: String) -> Self {
+        let (tx, rx) = mpsc::channel();
         let i3 = I3 {
-            senders: Arc::new(Mutex::new(HashMap::new())),
-            tree: Arc::new(Mutex::new(conn.get_tree().unwrap())),
+            shared: Arc::new(Mutex::new(Shared {
+                output,
+                state: State::default(),
+                senders: HashMap::new(),
+                dynamic: Vec::new(),
+                modes: Vec::new(),
+                focused: Vec::new(),
+            })),
+            commander: I3Commander::new(),
+            messages: tx.clone(),
         };
 
-        i3.listen_workspace();
-        i3.listen_windows();
+        let shared = Arc::clone(&i3.shared);
+        thread::spawn(move || listen_events(&shared, &tx, &rx));
 
         i3
     }
 
+    pub fn commander(&self) -> I3Commander {
+        self.commander.clone()
+    }
+
+    // Choose the icons of all windows again, after the icon rules have been replaced
+    pub fn refresh_icons(&self) {
+        let _ = self.messages.send(Message::Refresh);
+    }
+
+    // Drop all components, their threads stop once they notice the closed channel
+    pub fn clear(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.senders.clear();
+        shared.dynamic.clear();
+        shared.modes.clear();
+        shared.focused.clear();
+    }
+
+    // Receive the state of a workspace, starting with the current one
     pub fn add(&mut self, workspace: String, sender: Sender<I3Change>) {
-        let _ = sender.send(I3Change::new(Some(false), None));
-        let mut lock = self.senders.lock().unwrap();
-        lock.insert(workspace, I3Listener::new(sender));
-    }
-
-    fn listen_workspace(&self) {
-        let senders = Arc::clone(&self.senders);
-        thread::spawn(move || {
-            loop {
-                info!("Starting i3 workspace connection");
-                // Start normal connection for ws queries
-                let mut conn = i3ipc::I3Connection::connect().unwrap();
-
-                // Start event connection for ws events
-                let mut event_conn = i3ipc::I3EventListener::connect().unwrap();
-                let _ = event_conn.subscribe(&[i3ipc::Subscription::Workspace]);
-
-                for event in event_conn.listen() {
-                    let ws_event = match event {
-                        Ok(e) => match e {
-                            WorkspaceEvent(ws_event) => ws_event,
-                            _ => continue,
-                        },
-                        Err(e) => {
-                            error!("Workspace i3 connection closed: {}", e);
-                            thread::sleep(Duration::from_secs(1));
-                            break;
-                        }
-                    };
-
-                    if let Focus = ws_event.change {
-                        let workspaces = conn.get_workspaces().unwrap();
-                        let mut lock = senders.lock().unwrap();
-                        for workspace in workspaces.workspaces {
-                            if let Some(listener) = lock.get_mut(&workspace.name) {
-                                if workspace.visible != listener.old_state {
-                                    listener.old_state = workspace.visible;
-                                    let _ = listener
-                                        .sender
-                                        .send(I3Change::new(Some(workspace.visible), None));
-                                }
-                            }
-                        }
-                    } else if let Empty = ws_event.change {
-                        let mut lock = senders.lock().unwrap();
-                        let ws_name = ws_event.current.unwrap().name.unwrap();
-                        if let Some(listener) = lock.get_mut(&ws_name) {
-                            listener.old_state = false;
-                            let _ = listener.sender.send(I3Change::new(Some(false), None));
-                        }
-                    }
-                }
-            }
+        let mut shared = self.shared.lock().unwrap();
+        let mut listener = I3Listener::new(sender);
+        listener.update(&shared.state, &workspace, &shared.output);
+        shared.senders.insert(workspace, listener);
+    }
+
+    // Receive the name of the binding mode whenever it changes
+    pub fn add_mode(&mut self, sender: Sender<String>) {
+        let mut shared = self.shared.lock().unwrap();
+        if sender.send(shared.state.mode.clone()).is_ok() {
+            shared.modes.push(sender);
+        }
+    }
+
+    // Receive the focused window whenever it changes, `None` if nothing is focused
+    pub fn add_focused_window(&mut self, sender: Sender<Option<FocusedWindow>>) {
+        let mut shared = self.shared.lock().unwrap();
+        if sender.send(shared.state.focused.clone()).is_ok() {
+            shared.focused.push(sender);
+        }
+    }
+
+    // Add buttons which show the workspaces of `output`, ordered by number
+    //
+    // Buttons without a workspace are hidden.
+    pub fn add_dynamic(&mut self, output: String, senders: Vec<Sender<I3Change>>) {
+        let mut shared = self.shared.lock().unwrap();
+
+        // Replace the buttons of a previous layout
+        shared.dynamic.retain(|group| group.output != output);
+        shared.dynamic.push(DynamicWorkspaces {
+            output,
+            slots: senders.into_iter().map(|sender| (None, sender)).collect(),
         });
+        shared.assign_dynamic();
     }
+}
 
-    fn listen_windows(&self) {
-        let senders = Arc::clone(&self.senders);
-        let tree = Arc::clone(&self.tree);
-        thread::spawn(move || {
-            loop {
-                info!("Starting i3 window connection");
-                // Start normal connection for ws queries
-                let mut conn = i3ipc::I3Connection::connect().unwrap();
-
-                // Start event connection for ws events
-                let mut event_conn = i3ipc::I3EventListener::connect().unwrap();
-                let _ = event_conn.subscribe(&[i3ipc::Subscription::Window]);
-
-                for event in event_conn.listen() {
-                    let window_event = match event {
-                        Ok(e) => match e {
-                            WindowEvent(window_event) => window_event,
-                            _ => continue,
-                        },
-                        Err(e) => {
-                            error!("Window i3 connection closed: {}", e);
-                            thread::sleep(Duration::from_secs(1));
-                            break;
-                        }
-                    };
-
-                    let mut tree = tree.lock().unwrap();
-
-                    let mut workspace_states = Vec::new();
-                    let id = window_event.container.id;
-                    match window_event.change {
-                        New => {
-                            *tree = conn.get_tree().unwrap();
-                            let ws = workspace_state(&(*tree), id);
-                            if let Some(ws) = ws {
-                                workspace_states.push(ws);
-                            }
-                        }
-                        Move => {
-                            let ws = workspace_state(&(*tree), id);
-                            if let Some(mut ws) = ws {
-                                *tree = conn.get_tree().unwrap();
-                                ws.names = child_names_by_ws(&(*tree), &ws.workspace);
-                                workspace_states.push(ws);
-                            }
-
-                            let ws = workspace_state(&(*tree), id);
-                            if let Some(ws) = ws {
-                                workspace_states.push(ws);
-                            }
-                        }
-                        Close => {
-                            let ws = workspace_state(&(*tree), id);
-                            if let Some(mut ws) = ws {
-                                *tree = conn.get_tree().unwrap();
-                                ws.names = child_names_by_ws(&(*tree), &ws.workspace);
-                                workspace_states.push(ws);
-                            }
-                        }
-                        _ => continue,
-                    };
-
-                    for state in workspace_states {
-                        let name = if state.names.len() == 1 {
-                            state.names[0].clone()
-                        } else if state.names.is_empty() {
-                            "empty".into()
-                        } else {
-                            "mixed".into()
-                        };
-
-                        let mut lock = senders.lock().unwrap();
-                        if let Some(listener) = lock.get_mut(&state.workspace) {
-                            if listener.old_title != name {
-                                listener.old_title = name.clone();
-                                let _ = listener.sender.send(I3Change::new(None, Some(name)));
-                            }
-                        }
-                    }
+// Keep the shared state up to date with a single event connection
+//
+// Events are read on a separate thread, so other messages can be handled while
+// i3 is quiet.
+fn listen_events(shared: &Mutex<Shared>, tx: &Sender<Message>, rx: &Receiver<Message>) {
+    let current = Arc::new(AtomicUsize::new(0));
+    for connection in 0.. {
+        current.store(connection, Ordering::SeqCst);
+        info!("Starting i3 connection");
+        let subscriptions = [
+            Subscription::Workspace,
+            Subscription::Window,
+            Subscription::Mode,
+            Subscription::Output,
+            Subscription::Shutdown,
+        ];
+        let mut event_conn = listen(&subscriptions);
+        let mut conn = connect_with_backoff(i3ipc::I3Connection::connect);
+
+        // The tree is requested without i3ipc, it doesn't parse the app ids of Sway
+        let socket = match ipc::socket_path() {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("Unable to find the i3 socket: {}", e);
+                thread::sleep(Duration::from_millis(MAX_BACKOFF_MS));
+                continue;
+            }
+        };
+
+        let (mut state, mut tree) = match State::fetch(&mut conn, &socket) {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                error!("Unable to query i3 state: {}", e);
+                thread::sleep(Duration::from_millis(MAX_BACKOFF_MS));
+                continue;
+            }
+        };
+        shared.lock().unwrap().publish(state.clone());
+
+        let events = tx.clone();
+        let latest = Arc::clone(&current);
+        thread::spawn(move || forward_events(&mut event_conn, connection, &latest, &events));
+
+        for message in rx.iter() {
+            let result = match message {
+                // Events are handled in order, so every query sees the effects of earlier events
+                Message::Event(id, event) if id == connection => {
+                    handle_event(&mut conn, &socket, &mut state, &mut tree, event)
+                }
+                Message::Closed(id) if id == connection => break,
+                Message::Refresh => {
+                    state.apps = tree.workspace_apps();
+                    Ok(())
                 }
+                // Leftovers of a previous connection
+                _ => continue,
+            };
+
+            if let Err(e) = result {
+                error!("Unable to query i3 state: {}", e);
+                break;
             }
-        });
+
+            shared.lock().unwrap().publish(state.clone());
+        }
+
+        // Nothing is known while i3 is unavailable
+        let mut shared = shared.lock().unwrap();
+        let mut disconnected = shared.state.clone();
+        disconnected.mode = DEFAULT_MODE.into();
+        disconnected.focused = None;
+        disconnected.connected = false;
+        shared.publish(disconnected);
     }
 }
 
-struct WorkspaceState {
-    names: Vec<String>,
-    workspace: String,
+// Pass the events of a connection to the event thread until it is closed or replaced
+fn forward_events(
+    event_conn: &mut i3ipc::I3EventListener,
+    connection: usize,
+    current: &AtomicUsize,
+    tx: &Sender<Message>,
+) {
+    for event in event_conn.listen() {
+        if current.load(Ordering::SeqCst) != connection {
+            return;
+        }
+
+        match event {
+            Ok(ShutdownEvent(shutdown_event)) => {
+                info!("i3 is shutting down: {:?}", shutdown_event.change);
+                break;
+            }
+            Ok(event) => if tx.send(Message::Event(connection, event)).is_err() {
+                return;
+            },
+            Err(e) => {
+                error!("i3 connection closed: {}", e);
+                break;
+            }
+        }
+    }
+
+    let _ = tx.send(Message::Closed(connection));
 }
 
-fn workspace_state(node: &Node, id: i64) -> Option<WorkspaceState> {
-    if node.nodes.is_empty() {
-        return None;
+// Apply an event to the state, querying i3 for everything the event doesn't include
+fn handle_event(
+    conn: &mut i3ipc::I3Connection,
+    socket: &Path,
+    state: &mut State,
+    tree: &mut Tree,
+    event: Event,
+) -> Result<(), MessageError> {
+    match event {
+        WorkspaceEvent(ws_event) => {
+            // Focusing an empty workspace doesn't emit a window event
+            if let Focus = ws_event.change {
+                let is_empty = ws_event.current.as_ref().map_or(false, |current| {
+                    current.nodes.is_empty() && current.floating_nodes.is_empty()
+                });
+                if is_empty {
+                    state.focused = None;
+                }
+            }
+
+            state.update_workspaces(conn)?;
+        }
+        WindowEvent(window_event) => {
+            let container = &window_event.container;
+            match window_event.change {
+                WindowChange::Focus | Title if container.focused => {
+                    state.focused = Some(FocusedWindow::new(tree, container));
+                }
+                Close if container.focused => state.focused = None,
+                _ => (),
+            }
+
+            match window_event.change {
+                // Urgency is tracked per workspace by i3
+                WindowChange::Urgent => state.update_workspaces(conn)?,
+                // These can add, remove or rename windows of any workspace,
+                // moves might even involve a workspace on another output
+                New | Close | Move | Floating | Title | FullscreenMode => {
+                    *tree = Tree::fetch(socket)?;
+                    state.apps = tree.workspace_apps();
+                }
+                _ => (),
+            }
+        }
+        ModeEvent(mode_event) => state.mode = mode_event.change,
+        // Workspaces move to other outputs when an output is disabled
+        OutputEvent(_) => {
+            state.update_outputs(conn)?;
+            state.update_workspaces(conn)?;
+        }
+        _ => (),
     }
 
-    for n in &node.nodes {
-        if n.id == id {
-            let mut names = child_names(node);
-            names.sort();
-            names.dedup();
-            return Some(WorkspaceState {
-                names,
-                workspace: node.name.clone().unwrap_or_default(),
-            });
+    Ok(())
+}
+
+// Connect an event listener with the subscriptions, retrying until i3 is available
+fn listen(subscriptions: &[Subscription]) -> i3ipc::I3EventListener {
+    connect_with_backoff(|| -> Result<_, String> {
+        let mut listener = i3ipc::I3EventListener::connect().map_err(|e| e.to_string())?;
+        listener.subscribe(subscriptions).map_err(|e| e.to_string())?;
+        Ok(listener)
+    })
+}
+
+// Retry connecting, doubling the delay after every failure
+fn connect_with_backoff<T, E: Display, F: FnMut() -> Result<T, E>>(mut connect: F) -> T {
+    let mut delay = MIN_BACKOFF_MS;
+    loop {
+        match connect() {
+            Ok(conn) => return conn,
+            Err(e) => {
+                warn!("Unable to connect to i3, retrying in {}ms: {}", delay, e);
+                thread::sleep(Duration::from_millis(delay));
+                delay = (delay * 2).min(MAX_BACKOFF_MS);
+            }
         }
+    }
+}
+
+// Image name for a workspace with windows of these applications
+fn title(apps: &[AppCount]) -> String {
+    if apps.len() == 1 {
+        apps[0].name.clone()
+    } else if apps.is_empty() {
+        "empty".into()
+    } else {
+        "mixed".into()
+    }
+}
 
-        if let Some(workspace_state) = workspace_state(n, id) {
-            return Some(workspace_state);
+// Point i3ipc at the Sway socket when running on Sway
+//
+// Sway speaks the i3 IPC protocol, but i3ipc only looks for the i3 socket.
+// This has to be called before any connection is opened.
+pub fn detect_sway() {
+    if let Some(socket) = env::var_os("SWAYSOCK") {
+        info!("Running on Sway");
+        if env::var_os("I3SOCK").is_none() {
+            env::set_var("I3SOCK", socket);
         }
     }
+}
+
+// Names of all active outputs
+pub fn outputs() -> Result<Vec<String>, String> {
+    let mut conn = i3ipc::I3Connection::connect().map_err(|e| e.to_string())?;
+    let outputs = conn.get_outputs().map_err(|e| e.to_string())?;
+
+    Ok(outputs
+        .outputs
+        .into_iter()
+        .filter(|output| output.active)
+        .map(|output| output.name)
+        .collect())
+}
+
+// Name of the workspace holding the scratchpad windows
+const SCRATCHPAD: &str = "__i3_scratch";
 
-    None
+// Container of the layout tree, parsed from the reply to GET_TREE
+#[derive(Deserialize)]
+struct Container {
+    id: i64,
+    name: Option<String>,
+    #[serde(rename = "type")]
+    node_type: String,
+    focused: bool,
+    #[serde(default)]
+    nodes: Vec<Container>,
+    #[serde(default)]
+    floating_nodes: Vec<Container>,
+    // Properties of X11 windows
+    window_properties: Option<WindowProperties>,
+    // Application id of Wayland windows, only sent by Sway
+    app_id: Option<String>,
 }
 
-fn child_names_by_ws(node: &Node, name: &str) -> Vec<String> {
-    if node.nodes.is_empty() {
-        return Vec::new();
+#[derive(Deserialize)]
+struct WindowProperties {
+    class: Option<String>,
+    instance: Option<String>,
+}
+
+impl Container {
+    fn is_workspace(&self) -> bool {
+        self.node_type == "workspace"
+    }
+
+    fn class(&self) -> Option<&str> {
+        self.window_properties
+            .as_ref()
+            .and_then(|properties| properties.class.as_ref())
+            .map(String::as_str)
     }
 
-    if let Some(ref node_name) = node.name {
-        if node_name == name {
-            let mut names = child_names(node);
-            names.sort();
-            names.dedup();
-            return names;
+    fn instance(&self) -> Option<&str> {
+        self.window_properties
+            .as_ref()
+            .and_then(|properties| properties.instance.as_ref())
+            .map(String::as_str)
+    }
+
+    // X11 window class or Wayland app id
+    fn window_class(&self) -> Option<&str> {
+        self.class().or_else(|| self.app_id.as_ref().map(String::as_str))
+    }
+}
+
+// Snapshot of the layout tree
+struct Tree {
+    root: Container,
+}
+
+impl Tree {
+    // Request the tree in a single query, on Sway it includes the app ids
+    fn fetch(socket: &Path) -> Result<Self, MessageError> {
+        let reply = ipc::request(socket, ipc::GET_TREE, &[]).map_err(MessageError::Receive)?;
+        let root = serde_json::from_slice(&reply).map_err(MessageError::JsonCouldntParse)?;
+        Ok(Self { root })
+    }
+
+    // Icon name of a window, chosen by the icon rules
+    fn window_name(&self, container: &Container) -> String {
+        icons::icon(&Window {
+            title: container.name.as_ref().map_or("", String::as_str),
+            class: container.class(),
+            instance: container.instance(),
+            app_id: container.app_id.as_ref().map(String::as_str),
+        })
+    }
+
+    // Class of the window with the container id
+    fn class(&self, id: i64) -> Option<&str> {
+        find_container(&self.root, id)?.window_class()
+    }
+
+    // Applications on each workspace, the scratchpad is not a real workspace
+    fn workspace_apps(&self) -> HashMap<String, Vec<AppCount>> {
+        let mut workspaces = Vec::new();
+        find_workspaces(&self.root, &mut workspaces);
+
+        workspaces
+            .into_iter()
+            .filter_map(|workspace| {
+                let name = workspace.name.clone()?;
+                if name == SCRATCHPAD {
+                    return None;
+                }
+
+                let mut names = Vec::new();
+                for child in children(workspace) {
+                    self.window_names(child, &mut names);
+                }
+                Some((name, count_apps(names)))
+            })
+            .collect()
+    }
+
+    // The focused window, `None` if an empty workspace is focused
+    fn focused_window(&self) -> Option<FocusedWindow> {
+        find_focused(&self.root).map(|container| FocusedWindow {
+            title: container.name.clone().unwrap_or_default(),
+            class: container.window_class().map(str::to_owned),
+        })
+    }
+
+    // Icon names of all tiling and floating windows below a container
+    fn window_names(&self, container: &Container, names: &mut Vec<String>) {
+        if container.nodes.is_empty() && container.floating_nodes.is_empty() {
+            names.push(self.window_name(container));
+        }
+
+        for child in children(container) {
+            self.window_names(child, names);
         }
     }
+}
 
-    let mut names = Vec::new();
-    for n in &node.nodes {
-        names.append(&mut child_names_by_ws(n, name));
+fn find_focused(container: &Container) -> Option<&Container> {
+    // Empty workspaces don't have children either
+    let is_window = !container.is_workspace() && container.nodes.is_empty()
+        && container.floating_nodes.is_empty();
+    if container.focused && is_window {
+        return Some(container);
     }
 
-    names
+    children(container).filter_map(find_focused).next()
 }
 
-fn child_names(node: &Node) -> Vec<String> {
-    if node.nodes.is_empty() {
-        let name = node.name.clone().unwrap_or_default();
-        return vec![
-            if name.contains("Nightly") {
-                "firefox".into()
-            } else {
-                name
-            },
-        ];
+fn find_container(container: &Container, id: i64) -> Option<&Container> {
+    if container.id == id {
+        return Some(container);
+    }
+
+    children(container)
+        .filter_map(|child| find_container(child, id))
+        .next()
+}
+
+fn find_workspaces<'a>(container: &'a Container, workspaces: &mut Vec<&'a Container>) {
+    if container.is_workspace() {
+        workspaces.push(container);
+        return;
+    }
+
+    for child in children(container) {
+        find_workspaces(child, workspaces);
+    }
+}
+
+// Tiling and floating children of a container
+fn children(container: &Container) -> Chain<slice::Iter<Container>, slice::Iter<Container>> {
+    container.nodes.iter().chain(&container.floating_nodes)
+}
+
+// Group the names of windows by application
+fn count_apps(mut names: Vec<String>) -> Vec<AppCount> {
+    names.sort();
+
+    let mut apps: Vec<AppCount> = Vec::new();
+    for name in names {
+        match apps.last_mut() {
+            Some(ref mut app) if app.name == name => {
+                app.windows += 1;
+                continue;
+            }
+            _ => (),
+        }
+
+        apps.push(AppCount { name, windows: 1 });
+    }
+
+    apps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock_i3::{self, workspace, workspace_node, window, MockI3};
+    use mock_i3::{MODE_EVENT, WINDOW_EVENT, WORKSPACE_EVENT};
+    use std::sync::mpsc::{self, Receiver};
+    use serde_json::Value;
+
+    const TIMEOUT_MS: u64 = 2000;
+
+    // Buttons for the workspaces 1 and 2 connected to a mock i3
+    struct Harness {
+        mock: MockI3,
+        i3: I3,
+        modes: Receiver<String>,
+        ws1: Receiver<I3Change>,
+        ws2: Receiver<I3Change>,
+    }
+
+    impl Harness {
+        // Workspace 1 is focused and has a termite window, workspace 2 doesn't exist yet
+        fn start() -> Self {
+            let mut mock = MockI3::bind();
+            mock.set_workspaces(json!([workspace(1, true, false)]));
+            mock.set_tree(mock_i3::tree(vec![
+                workspace_node(1, vec![window(1001, "Termite", true)]),
+            ]));
+
+            // Register the buttons before i3 answers, so the initial sync is always the same
+            let mut i3 = I3::new(mock_i3::OUTPUT.into());
+            let (tx1, ws1) = mpsc::channel();
+            let (tx2, ws2) = mpsc::channel();
+            let (mode_tx, modes) = mpsc::channel();
+            i3.add("1".into(), tx1);
+            i3.add("2".into(), tx2);
+            i3.add_mode(mode_tx);
+            mock.serve();
+
+            let harness = Harness {
+                mock,
+                i3,
+                modes,
+                ws1,
+                ws2,
+            };
+            assert_eq!(harness.mode(), DEFAULT_MODE);
+
+            for rx in &[&harness.ws1, &harness.ws2] {
+                expect(rx, I3Change {
+                    connected: Some(false),
+                    ..I3Change::default()
+                });
+            }
+
+            expect(&harness.ws1, I3Change {
+                state: Some(WorkspaceStatus::Focused),
+                title: Some("termite".into()),
+                apps: Some(apps(&[("termite", 1)])),
+                connected: Some(true),
+                ..I3Change::default()
+            });
+            expect(&harness.ws2, I3Change {
+                connected: Some(true),
+                ..I3Change::default()
+            });
+
+            harness
+        }
+
+        fn mode(&self) -> String {
+            self.modes
+                .recv_timeout(Duration::from_millis(TIMEOUT_MS))
+                .expect("no mode received")
+        }
+
+        fn workspace_event(&self, change: &str, current: Value, old: Value) {
+            let event = json!({ "change": change, "current": current, "old": old });
+            self.mock.event(WORKSPACE_EVENT, event);
+        }
+
+        fn window_event(&self, change: &str, container: Value) {
+            let event = json!({ "change": change, "container": container });
+            self.mock.event(WINDOW_EVENT, event);
+        }
+
+        // Events are handled in order, so all changes of earlier events have been
+        // sent once the mode of a later event arrives
+        fn expect_nothing(&self) {
+            for mode in &["resize", DEFAULT_MODE] {
+                self.mock.event(MODE_EVENT, json!({ "change": mode }));
+                assert_eq!(self.mode(), *mode);
+            }
+
+            assert_eq!(self.ws1.try_recv().ok(), None);
+            assert_eq!(self.ws2.try_recv().ok(), None);
+        }
+    }
+
+    fn apps(apps: &[(&str, usize)]) -> Vec<AppCount> {
+        apps.iter()
+            .map(|&(name, windows)| AppCount {
+                name: name.into(),
+                windows,
+            })
+            .collect()
+    }
+
+    fn expect(rx: &Receiver<I3Change>, expected: I3Change) {
+        let change = rx.recv_timeout(Duration::from_millis(TIMEOUT_MS))
+            .expect("no change received");
+        assert_eq!(change, expected);
+    }
+
+    fn status(status: WorkspaceStatus) -> I3Change {
+        I3Change {
+            state: Some(status),
+            ..I3Change::default()
+        }
     }
 
-    let mut names = Vec::new();
-    for n in &node.nodes {
-        names.append(&mut child_names(n));
+    #[test]
+    fn initial_sync() {
+        let harness = Harness::start();
+        harness.expect_nothing();
     }
 
-    names
+    #[test]
+    fn new_window() {
+        let harness = Harness::start();
+
+        harness.mock.set_tree(mock_i3::tree(vec![
+            workspace_node(
+                1,
+                vec![window(1001, "Termite", false), window(1002, "Firefox", true)],
+            ),
+        ]));
+        harness.window_event("new", window(1002, "Firefox", true));
+
+        expect(&harness.ws1, I3Change {
+            title: Some("mixed".into()),
+            apps: Some(apps(&[("firefox", 1), ("termite", 1)])),
+            ..I3Change::default()
+        });
+        harness.expect_nothing();
+    }
+
+    #[test]
+    fn move_window() {
+        let harness = Harness::start();
+
+        let workspaces = json!([workspace(1, true, false), workspace(2, false, false)]);
+        harness.mock.set_workspaces(workspaces);
+        harness.mock.set_tree(mock_i3::tree(vec![
+            workspace_node(1, Vec::new()),
+            workspace_node(2, vec![window(1001, "Termite", false)]),
+        ]));
+        harness.workspace_event("init", workspace_node(2, Vec::new()), Value::Null);
+        harness.window_event("move", window(1001, "Termite", false));
+
+        expect(&harness.ws1, I3Change {
+            title: Some("empty".into()),
+            apps: Some(Vec::new()),
+            ..I3Change::default()
+        });
+        expect(&harness.ws2, I3Change {
+            title: Some("termite".into()),
+            apps: Some(apps(&[("termite", 1)])),
+            ..I3Change::default()
+        });
+        harness.expect_nothing();
+    }
+
+    #[test]
+    fn close_window() {
+        let harness = Harness::start();
+
+        harness.mock.set_tree(mock_i3::tree(vec![workspace_node(1, Vec::new())]));
+        harness.window_event("close", window(1001, "Termite", true));
+
+        expect(&harness.ws1, I3Change {
+            title: Some("empty".into()),
+            apps: Some(Vec::new()),
+            ..I3Change::default()
+        });
+        harness.expect_nothing();
+    }
+
+    #[test]
+    fn focus_workspace() {
+        let harness = Harness::start();
+
+        let workspaces = json!([workspace(1, false, false), workspace(2, true, false)]);
+        harness.mock.set_workspaces(workspaces);
+        harness.mock.set_tree(mock_i3::tree(vec![
+            workspace_node(1, vec![window(1001, "Termite", false)]),
+            workspace_node(2, Vec::new()),
+        ]));
+        harness.workspace_event(
+            "focus",
+            workspace_node(2, Vec::new()),
+            workspace_node(1, vec![window(1001, "Termite", false)]),
+        );
+
+        expect(&harness.ws1, status(WorkspaceStatus::Hidden));
+        expect(&harness.ws2, status(WorkspaceStatus::Focused));
+        harness.expect_nothing();
+    }
+
+    #[test]
+    fn empty_workspace() {
+        let harness = Harness::start();
+
+        // Focus the new workspace 2, then go back which makes i3 remove it
+        let workspaces = json!([workspace(1, false, false), workspace(2, true, false)]);
+        harness.mock.set_workspaces(workspaces);
+        harness.workspace_event(
+            "focus",
+            workspace_node(2, Vec::new()),
+            workspace_node(1, vec![window(1001, "Termite", false)]),
+        );
+
+        expect(&harness.ws1, status(WorkspaceStatus::Hidden));
+        expect(&harness.ws2, status(WorkspaceStatus::Focused));
+
+        harness.mock.set_workspaces(json!([workspace(1, true, false)]));
+        harness.workspace_event(
+            "focus",
+            workspace_node(1, vec![window(1001, "Termite", false)]),
+            workspace_node(2, Vec::new()),
+        );
+        harness.workspace_event("empty", workspace_node(2, Vec::new()), Value::Null);
+
+        expect(&harness.ws1, status(WorkspaceStatus::Focused));
+        expect(&harness.ws2, status(WorkspaceStatus::Hidden));
+        harness.expect_nothing();
+    }
+
+    #[test]
+    fn floating_and_scratchpad_windows() {
+        let mut harness = Harness::start();
+        let (tx, scratchpad) = mpsc::channel();
+        harness.i3.add(SCRATCHPAD.into(), tx);
+
+        // Floating windows count, windows in the scratchpad don't
+        let mut tree = mock_i3::tree(vec![workspace_node(1, Vec::new())]);
+        tree["nodes"][1]["nodes"][0]["nodes"][0]["floating_nodes"] =
+            json!([window(1001, "Termite", true)]);
+        tree["nodes"][0]["nodes"][0]["nodes"][0]["floating_nodes"] =
+            json!([window(1002, "Firefox", false)]);
+        harness.mock.set_tree(tree);
+        harness.window_event("floating", window(1001, "Termite", true));
+
+        harness.expect_nothing();
+        assert_eq!(scratchpad.try_recv().ok(), None);
+    }
+
+    #[test]
+    fn nested_floating_container() {
+        // Floating windows which have been tiled inside a floating container
+        let mut floating_con = window(1100, "Firefox", false);
+        floating_con["type"] = json!("floating_con");
+        floating_con["nodes"] = json!([
+            window(1002, "Firefox", false),
+            window(1003, "Termite", true),
+        ]);
+        let mut workspace = workspace_node(1, vec![window(1001, "Termite", false)]);
+        workspace["floating_nodes"] = json!([floating_con]);
+
+        let mut scratch_window = window(1004, "Firefox", false);
+        scratch_window["type"] = json!("floating_con");
+        let mut tree = mock_i3::tree(vec![workspace]);
+        tree["nodes"][0]["nodes"][0]["nodes"][0]["floating_nodes"] = json!([scratch_window]);
+
+        let tree = Tree {
+            root: serde_json::from_value(tree).unwrap(),
+        };
+        let mut expected = HashMap::new();
+        expected.insert("1".to_string(), apps(&[("firefox", 1), ("termite", 2)]));
+        assert_eq!(tree.workspace_apps(), expected);
+
+        let focused = tree.focused_window().expect("no focused window");
+        assert_eq!(focused.class, Some("Termite".into()));
+        assert_eq!(tree.class(1002), Some("Firefox"));
+    }
+
+    #[test]
+    fn count_apps_by_name() {
+        let names = vec!["termite", "firefox", "termite", "gimp", "termite"];
+        let counted = count_apps(names.into_iter().map(String::from).collect());
+        assert_eq!(counted, apps(&[("firefox", 1), ("gimp", 1), ("termite", 3)]));
+        assert_eq!(count_apps(Vec::new()), Vec::new());
+    }
 }
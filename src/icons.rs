@@ -0,0 +1,166 @@
+use config::{self, ConfigError};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::io::Read;
+use std::fs::File;
+use regex::Regex;
+use toml;
+
+// Rules used when there is no rules file
+const DEFAULT_RULES: &str = r#"
+[[rules]]
+class = "(?i)^termite$"
+icon = "termite"
+
+[[rules]]
+class = "(?i)firefox"
+icon = "firefox"
+
+[[rules]]
+title = "Nightly"
+icon = "firefox"
+"#;
+
+// Create a global because the rules are needed by every i3 thread
+lazy_static! {
+    static ref RULES: RwLock<IconRules> = RwLock::new(IconRules::default());
+}
+
+// Replace the rules used for choosing workspace icons
+pub fn set_rules(rules: IconRules) {
+    *RULES.write().unwrap() = rules;
+}
+
+// Icon for a window, falls back to the window title if no rule matches
+pub fn icon(window: &Window) -> String {
+    let rules = RULES.read().unwrap();
+    rules
+        .rules
+        .iter()
+        .find(|rule| rule.matches(window))
+        .map(|rule| rule.icon.clone())
+        .unwrap_or_else(|| window.title.to_owned())
+}
+
+// Properties of a window which can be matched by a rule
+pub struct Window<'a> {
+    pub title: &'a str,
+    pub class: Option<&'a str>,
+    pub instance: Option<&'a str>,
+    pub app_id: Option<&'a str>,
+}
+
+pub struct IconRules {
+    rules: Vec<IconRule>,
+}
+
+impl Default for IconRules {
+    fn default() -> Self {
+        Self::parse(DEFAULT_RULES, Path::new("<default>")).unwrap()
+    }
+}
+
+impl IconRules {
+    // Load rules from `path` or the default location, using the default rules if there is no file
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(ref path) if path.exists() => path.clone(),
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let mut content = String::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .map_err(|e| ConfigError::Io(path.clone(), e))?;
+
+        Self::parse(&content, &path)
+    }
+
+    fn parse(content: &str, path: &Path) -> Result<Self, ConfigError> {
+        let raw: RawRules =
+            toml::from_str(content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+
+        let mut rules = Vec::new();
+        for (i, rule) in raw.rules.into_iter().enumerate() {
+            rules.push(IconRule::new(rule, &format!("rules[{}]", i))?);
+        }
+
+        Ok(Self { rules })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRules {
+    #[serde(default)]
+    rules: Vec<RawRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    icon: String,
+    title: Option<String>,
+    class: Option<String>,
+    instance: Option<String>,
+    app_id: Option<String>,
+}
+
+// Rule matching windows by regex, all specified properties have to match
+struct IconRule {
+    icon: String,
+    title: Option<Regex>,
+    class: Option<Regex>,
+    instance: Option<Regex>,
+    app_id: Option<Regex>,
+}
+
+impl IconRule {
+    fn new(raw: RawRule, key: &str) -> Result<Self, ConfigError> {
+        let compile = |regex: Option<String>, field: &str| match regex {
+            Some(regex) => Regex::new(&regex)
+                .map(Some)
+                .map_err(|e| ConfigError::invalid(format!("{}.{}", key, field), e.to_string())),
+            None => Ok(None),
+        };
+
+        let rule = Self {
+            icon: raw.icon,
+            title: compile(raw.title, "title")?,
+            class: compile(raw.class, "class")?,
+            instance: compile(raw.instance, "instance")?,
+            app_id: compile(raw.app_id, "app_id")?,
+        };
+
+        if rule.title.is_none() && rule.class.is_none() && rule.instance.is_none()
+            && rule.app_id.is_none()
+        {
+            return Err(ConfigError::invalid(
+                key,
+                "needs at least one of `title`, `class`, `instance` or `app_id`",
+            ));
+        }
+
+        Ok(rule)
+    }
+
+    fn matches(&self, window: &Window) -> bool {
+        // Properties the window doesn't have never match
+        let is_match = |regex: &Option<Regex>, value: Option<&str>| match *regex {
+            Some(ref regex) => value.map_or(false, |value| regex.is_match(value)),
+            None => true,
+        };
+
+        is_match(&self.title, Some(window.title)) && is_match(&self.class, window.class)
+            && is_match(&self.instance, window.instance)
+            && is_match(&self.app_id, window.app_id)
+    }
+}
+
+// Location of the rules file, `$XDG_CONFIG_HOME/leechbar/icons.toml`
+pub fn default_path() -> Option<PathBuf> {
+    config::config_dir().map(|dir| dir.join("icons.toml"))
+}
@@ -1,37 +1,139 @@
+use image::{imageops, DynamicImage, FilterType};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use leechbar::{Bar, Image};
-use std::path::PathBuf;
-use image;
+use std::path::{Path, PathBuf};
+use assets::Assets;
+
+// Images are cached by name and the color they have been tinted with
+type CacheKey = (PathBuf, Option<[u8; 4]>);
 
 #[derive(Clone)]
 pub struct ImageCache {
     bar: Bar,
-    cache: Arc<Mutex<HashMap<PathBuf, Image>>>,
+    assets: Assets,
+    cache: Arc<Mutex<HashMap<CacheKey, Image>>>,
+    // Composited images by the names of their background and icons
+    stacks: Arc<Mutex<HashMap<Vec<PathBuf>, Image>>>,
 }
 
 impl ImageCache {
-    pub fn new(bar: Bar) -> Self {
+    pub fn new(bar: Bar, assets: Assets) -> Self {
         Self {
             bar,
+            assets,
             cache: Arc::new(Mutex::new(HashMap::new())),
+            stacks: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
-    pub fn get<T: Into<PathBuf>>(&self, path: T) -> Option<Image> {
-        let path = path.into();
+    // Get an image by its name relative to the asset directories
+    pub fn get<T: Into<PathBuf>>(&self, name: T) -> Option<Image> {
+        self.load((name.into(), None))
+    }
+
+    // Get an image with a color blended over it, the alpha channel sets the strength
+    pub fn get_tinted<T: Into<PathBuf>>(&self, name: T, tint: [u8; 4]) -> Option<Image> {
+        self.load((name.into(), Some(tint)))
+    }
+
+    // Name of the image stored at `path`, if it is in one of the asset directories
+    pub fn name(&self, path: &Path) -> Option<PathBuf> {
+        self.assets.name(path)
+    }
+
+    // Drop the image stored at `path` so it is resolved again on the next access
+    pub fn invalidate(&self, path: &Path) {
+        if let Some(name) = self.assets.name(path) {
+            let mut lock = self.cache.lock().unwrap();
+            lock.retain(|&(ref cached, _), _| *cached != name);
+
+            let mut lock = self.stacks.lock().unwrap();
+            lock.retain(|names, _| !names.contains(&name));
+        }
+    }
+
+    // Get a background image with the icons drawn next to each other on top of it
+    //
+    // Icons which don't exist are skipped.
+    pub fn get_stacked<T: Into<PathBuf>>(&self, background: T, icons: &[PathBuf]) -> Option<Image> {
+        let mut key = vec![background.into()];
+        key.extend_from_slice(icons);
+
+        let mut lock = self.stacks.lock().unwrap();
+        if let Some(image) = lock.get(&key) {
+            return Some(image.clone());
+        }
+
+        let background = self.assets.image(&key[0])?;
+        let icons: Vec<DynamicImage> = icons
+            .iter()
+            .filter_map(|icon| self.assets.image(icon))
+            .collect();
+
+        let img = stacked(&background, &icons);
+        let image = Image::new(&self.bar, &img).unwrap();
+        lock.insert(key, image.clone());
+        Some(image)
+    }
+
+    fn load(&self, key: CacheKey) -> Option<Image> {
         let mut lock = self.cache.lock().unwrap();
 
-        if let Some(image) = lock.get(&path) {
+        if let Some(image) = lock.get(&key) {
             return Some(image.clone());
         }
 
-        if let Ok(img) = image::open(&path) {
+        if let Some(mut img) = self.assets.image(&key.0) {
+            if let Some(tint) = key.1 {
+                img = tinted(&img, tint);
+            }
+
             let image = Image::new(&self.bar, &img).unwrap();
-            lock.insert(path.clone(), image.clone());
+            lock.insert(key, image.clone());
             Some(image)
         } else {
             None
         }
     }
 }
+
+fn tinted(img: &DynamicImage, tint: [u8; 4]) -> DynamicImage {
+    let mut buffer = img.to_rgba();
+    let alpha = u16::from(tint[3]);
+
+    for pixel in buffer.pixels_mut() {
+        for i in 0..3 {
+            let blended = u16::from(pixel.data[i]) * (255 - alpha) + u16::from(tint[i]) * alpha;
+            pixel.data[i] = (blended / 255) as u8;
+        }
+    }
+
+    DynamicImage::ImageRgba8(buffer)
+}
+
+// Scale the icons down and center them next to each other on the background
+fn stacked(background: &DynamicImage, icons: &[DynamicImage]) -> DynamicImage {
+    let mut buffer = background.to_rgba();
+
+    // Every icon needs at least one pixel
+    let (width, height) = buffer.dimensions();
+    let icons = &icons[..icons.len().min(width as usize)];
+    if icons.is_empty() || height == 0 {
+        return DynamicImage::ImageRgba8(buffer);
+    }
+
+    let size = (height * 2 / 3).min(width / icons.len() as u32).max(1);
+    let mut x = (width - size * icons.len() as u32) / 2;
+
+    for icon in icons {
+        let icon = icon.resize(size, size, FilterType::Triangle).to_rgba();
+        let (icon_width, icon_height) = icon.dimensions();
+        let left = x + (size - icon_width) / 2;
+        let top = (height - icon_height) / 2;
+        imageops::overlay(&mut buffer, &icon, left, top);
+        x += size;
+    }
+
+    DynamicImage::ImageRgba8(buffer)
+}
@@ -0,0 +1,61 @@
+// Minimal client for the i3 IPC protocol
+//
+// This is only used for replies which i3ipc doesn't parse completely, like the
+// layout tree which has the Wayland `app_id` of windows on Sway.
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use std::os::unix::net::UnixStream;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::env;
+
+pub const MAGIC: &[u8] = b"i3-ipc";
+pub const GET_TREE: u32 = 4;
+
+// Send a message and read the reply payload
+pub fn request(socket: &Path, message_type: u32, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = UnixStream::connect(socket)?;
+    write_message(&mut stream, message_type, payload)?;
+    read_message(&mut stream).map(|(_, payload)| payload)
+}
+
+// Messages are the magic string followed by length and type in native byte order
+pub fn write_message<W: Write>(writer: &mut W, message_type: u32, payload: &[u8]) -> io::Result<()> {
+    let mut message = Vec::with_capacity(MAGIC.len() + 8 + payload.len());
+    message.extend_from_slice(MAGIC);
+    message.write_u32::<NativeEndian>(payload.len() as u32)?;
+    message.write_u32::<NativeEndian>(message_type)?;
+    message.extend_from_slice(payload);
+    writer.write_all(&message)
+}
+
+pub fn read_message<R: Read>(reader: &mut R) -> io::Result<(u32, Vec<u8>)> {
+    let mut magic = [0; 6];
+    reader.read_exact(&mut magic)?;
+
+    if magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid i3 ipc magic"));
+    }
+
+    let len = reader.read_u32::<NativeEndian>()?;
+    let message_type = reader.read_u32::<NativeEndian>()?;
+
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+
+    Ok((message_type, payload))
+}
+
+// Socket of the running i3 or Sway, found the same way i3ipc finds it
+pub fn socket_path() -> io::Result<PathBuf> {
+    if let Some(socket) = env::var_os("I3SOCK") {
+        return Ok(socket.into());
+    }
+
+    let output = Command::new("i3").arg("--get-socketpath").output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, "i3 --get-socketpath failed"));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().into())
+}
@@ -0,0 +1,346 @@
+use leechbar::{Alignment, Background, Bar, Component, Event, Foreground, Width};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use workspace_component::{Strip, Workspace};
+use config::{BarConfig, ComponentConfig, Config, WorkspaceMode};
+use volume_slider::VolumeSlider;
+use microphone_component::Microphone;
+use recording_component::Recording;
+use icons::{self, IconRules};
+use image_cache::ImageCache;
+use time_component::Time;
+use mode_component::Mode;
+use window_component::WindowTitle;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::path::{Path, PathBuf};
+use std::thread;
+use i3::I3;
+use chan;
+
+pub type BoxedComponent = Box<Component + Send>;
+
+// Component wrapper which allows replacing the component after it has been added to the bar
+//
+// The bar places components by their alignment when they are added, so a slot
+// only takes components with the alignment of its first one.
+pub struct Slot {
+    component: Arc<Mutex<Option<BoxedComponent>>>,
+    alignment: Alignment,
+    redraw: chan::Receiver<()>,
+}
+
+// Handle for replacing the component of a slot from another thread
+#[derive(Clone)]
+pub struct SlotHandle {
+    component: Arc<Mutex<Option<BoxedComponent>>>,
+    alignment: Alignment,
+    generation: Arc<AtomicUsize>,
+    redraw: chan::Sender<()>,
+}
+
+impl Slot {
+    pub fn new(component: BoxedComponent) -> (Self, SlotHandle) {
+        // Async channel so redraws can be requested before the event loop is running
+        let (tx, rx) = chan::async();
+        let alignment = component.alignment();
+
+        let handle = SlotHandle {
+            component: Arc::new(Mutex::new(None)),
+            alignment,
+            generation: Arc::new(AtomicUsize::new(0)),
+            redraw: tx,
+        };
+        handle.replace(Some(component));
+
+        let slot = Slot {
+            component: Arc::clone(&handle.component),
+            alignment,
+            redraw: rx,
+        };
+
+        (slot, handle)
+    }
+}
+
+impl SlotHandle {
+    // Swap out the component and redraw the slot
+    pub fn replace(&self, component: Option<BoxedComponent>) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+
+        let mut lock = self.component.lock().unwrap();
+        *lock = component;
+
+        // Forward the redraw requests of the new component until it is replaced again
+        if let Some(ref mut component) = *lock {
+            let receiver = component.redraw_timer();
+            let current_generation = Arc::clone(&self.generation);
+            let sender = self.redraw.clone();
+            thread::spawn(move || {
+                while let Some(()) = receiver.recv() {
+                    sender.send(());
+                    if current_generation.load(Ordering::SeqCst) != generation {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(lock);
+
+        self.redraw();
+    }
+
+    pub fn redraw(&self) {
+        self.redraw.send(());
+    }
+}
+
+impl Component for Slot {
+    fn update(&mut self) -> bool {
+        match *self.component.lock().unwrap() {
+            Some(ref mut component) => component.update(),
+            None => true,
+        }
+    }
+
+    fn redraw_timer(&mut self) -> chan::Receiver<()> {
+        self.redraw.clone()
+    }
+
+    fn event(&mut self, event: Event) -> bool {
+        match *self.component.lock().unwrap() {
+            Some(ref mut component) => component.event(event),
+            None => false,
+        }
+    }
+
+    fn background(&self) -> Background {
+        match *self.component.lock().unwrap() {
+            Some(ref component) => component.background(),
+            None => Background::new(),
+        }
+    }
+
+    fn foreground(&self) -> Foreground {
+        match *self.component.lock().unwrap() {
+            Some(ref component) => component.foreground(),
+            None => Foreground::new(),
+        }
+    }
+
+    fn alignment(&self) -> Alignment {
+        self.alignment
+    }
+
+    // Empty slots take up no space
+    fn width(&self) -> Width {
+        match *self.component.lock().unwrap() {
+            Some(ref component) => component.width(),
+            None => Width::new().fixed(0),
+        }
+    }
+}
+
+// All components of the bar, rebuilt from the config on reload
+pub struct Layout {
+    bar: Bar,
+    output: String,
+    ws_offset: i32,
+    ws_count: Option<i32>,
+    i3: Option<I3>,
+    slots: Vec<SlotHandle>,
+    image_cache: ImageCache,
+    // Settings the bar has been created with, these can't be changed on reload
+    bar_config: BarConfig,
+}
+
+impl Layout {
+    // Components are added to `bar`, it has to be the bar which runs the event loop
+    // or a clone of it which hasn't been used for adding components
+    pub fn new(
+        bar: Bar,
+        image_cache: ImageCache,
+        config: &Config,
+        output: String,
+        ws_offset: i32,
+        ws_count: Option<i32>,
+    ) -> Self {
+        Self {
+            bar,
+            output,
+            ws_offset,
+            ws_count,
+            image_cache,
+            i3: None,
+            slots: Vec::new(),
+            bar_config: config.bar.clone(),
+        }
+    }
+
+    // Add one slot for every configured component to the bar
+    pub fn populate(&mut self, config: &Config) {
+        for component in self.build(config) {
+            self.add(component);
+        }
+    }
+
+    // Rebuild all components from a new config, keeping the i3 and pulse connections alive
+    pub fn reload(&mut self, config: &Config) {
+        if config.bar != self.bar_config {
+            warn!("Restart required to apply changes of the `[bar]` section");
+        }
+
+        // Stop updating the old components, dynamic buttons would take over new ones otherwise
+        if let Some(ref mut i3) = self.i3 {
+            i3.clear();
+        }
+
+        // Fill the slots of every alignment in order, the bar only grows
+        let mut slots: Vec<SlotHandle> = self.slots.clone();
+        for component in self.build(config) {
+            let alignment = component.alignment();
+            match slots.iter().position(|slot| slot.alignment == alignment) {
+                Some(index) => slots.remove(index).replace(Some(component)),
+                None => self.add(component),
+            }
+        }
+
+        // Slots which are not needed anymore take up no space
+        for slot in slots {
+            slot.replace(None);
+        }
+    }
+
+    // Invalidate a changed image and redraw everything which might be using it
+    pub fn reload_image(&self, path: &Path) {
+        info!("Reloading image {:?}", path);
+        self.image_cache.invalidate(path);
+
+        let background = PathBuf::from(&self.bar_config.background);
+        if self.image_cache.name(path).as_ref() == Some(&background) {
+            warn!("Restart required to apply changes of the bar background");
+        }
+
+        self.redraw();
+    }
+
+    // Request a redraw of every component
+    pub fn redraw(&self) {
+        for slot in &self.slots {
+            slot.redraw();
+        }
+    }
+
+    fn add(&mut self, component: BoxedComponent) {
+        let (slot, handle) = Slot::new(component);
+        self.slots.push(handle);
+        self.bar.add(slot);
+    }
+
+    fn build(&mut self, config: &Config) -> Vec<BoxedComponent> {
+        let mut components: Vec<BoxedComponent> = Vec::new();
+
+        for component in &config.components {
+            match *component {
+                ComponentConfig::Workspaces(ref ws_config) => {
+                    match IconRules::load(ws_config.rules.as_ref().map(|path| path.as_path())) {
+                        Ok(rules) => icons::set_rules(rules),
+                        Err(e) => error!("{}", e),
+                    }
+
+                    let i3 = connect_i3(&mut self.i3, &self.output);
+                    i3.refresh_icons();
+                    let count = self.ws_count.unwrap_or(ws_config.count);
+
+                    let strip = Strip::default();
+                    let mut senders = Vec::new();
+                    for i in 0..count {
+                        let name = match ws_config.mode {
+                            WorkspaceMode::Fixed => {
+                                Some((i * ws_config.step + self.ws_offset).to_string())
+                            }
+                            WorkspaceMode::Dynamic => None,
+                        };
+
+                        let (tx, rx) = mpsc::channel();
+                        match name {
+                            Some(ref name) => i3.add(name.clone(), tx),
+                            None => senders.push(tx),
+                        }
+
+                        let ws = Workspace::new(
+                            self.bar.clone(),
+                            name,
+                            ws_config,
+                            self.image_cache.clone(),
+                            i3.commander(),
+                            Arc::clone(&strip),
+                            rx,
+                        );
+                        components.push(Box::new(ws));
+                    }
+
+                    if ws_config.mode == WorkspaceMode::Dynamic {
+                        i3.add_dynamic(self.output.clone(), senders);
+                    }
+                }
+                ComponentConfig::Time(ref time_config) => {
+                    let time = Time::new(self.bar.clone(), self.image_cache.clone(), time_config);
+                    components.push(Box::new(time));
+                }
+                ComponentConfig::Volume(ref vol_config) => {
+                    let vol = VolumeSlider::new(
+                        self.bar.clone(),
+                        self.image_cache.clone(),
+                        vol_config.x,
+                        vol_config.y,
+                        vol_config.width,
+                        vol_config.height,
+                    );
+                    components.push(Box::new(vol));
+                }
+                ComponentConfig::Microphone(ref mic_config) => {
+                    let cache = self.image_cache.clone();
+                    let mic = Microphone::new(self.bar.clone(), cache, mic_config);
+                    components.push(Box::new(mic));
+                }
+                ComponentConfig::Recording(ref rec_config) => {
+                    let rec = Recording::new(
+                        self.bar.clone(),
+                        self.image_cache.clone(),
+                        rec_config,
+                        config.bar.height,
+                    );
+                    components.push(Box::new(rec));
+                }
+                ComponentConfig::Mode(ref mode_config) => {
+                    let (tx, rx) = mpsc::channel();
+                    connect_i3(&mut self.i3, &self.output).add_mode(tx);
+
+                    let cache = self.image_cache.clone();
+                    let mode = Mode::new(self.bar.clone(), cache, mode_config, rx);
+                    components.push(Box::new(mode));
+                }
+                ComponentConfig::WindowTitle(ref title_config) => {
+                    let (tx, rx) = mpsc::channel();
+                    connect_i3(&mut self.i3, &self.output).add_focused_window(tx);
+
+                    let title = WindowTitle::new(
+                        self.bar.clone(),
+                        self.image_cache.clone(),
+                        config.bar.font.clone(),
+                        title_config,
+                        rx,
+                    );
+                    components.push(Box::new(title));
+                }
+            }
+        }
+
+        components
+    }
+}
+
+// Only connect to i3 when a component needs it
+fn connect_i3<'a>(i3: &'a mut Option<I3>, output: &str) -> &'a mut I3 {
+    i3.get_or_insert_with(|| I3::new(output.to_owned()))
+}
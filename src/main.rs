@@ -1,4 +1,8 @@
+extern crate byteorder;
+extern crate cairo;
 extern crate chan;
+#[macro_use]
+extern crate clap;
 extern crate env_logger;
 extern crate i3ipc;
 extern crate image;
@@ -9,68 +13,122 @@ extern crate libc;
 extern crate libpulse_sys;
 #[macro_use]
 extern crate log;
+extern crate notify;
+extern crate pango;
+extern crate pangocairo;
+extern crate regex;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[cfg_attr(test, macro_use)]
+extern crate serde_json;
 extern crate time;
+extern crate toml;
 
 
 mod workspace_component;
 mod time_component;
+mod mode_component;
+mod window_component;
 mod volume_slider;
+mod microphone_component;
+mod recording_component;
+mod pulse;
 mod image_cache;
+mod assets;
+mod reload;
+mod config;
+mod layout;
+mod icons;
+mod cli;
+mod ipc;
 mod i3;
+#[cfg(test)]
+mod mock_i3;
 
-use workspace_component::Workspace;
-use volume_slider::VolumeSlider;
 use image_cache::ImageCache;
-use time_component::Time;
+use assets::Assets;
 use leechbar::*;
-use std::env;
-use i3::I3;
-
-const BG: &str = "./images/bg.png";
-const FONT: &str = "Fira Sans 12";
-const NAME: &str = "LeechBar";
+use config::Config;
+use layout::Layout;
+use std::process;
 
 fn main() {
     env_logger::init().unwrap();
 
-    // Get output and ws offset
-    let output = env::args().nth(1).expect("Please specify an output");
-    let ws_offset = env::args().nth(2).expect("Please spcify ws offset");
-    let ws_offset = i32::from_str_radix(&ws_offset, 10).unwrap();
+    let options = cli::parse();
 
-    let bg_img = image::open(BG).unwrap();
-    let mut bar = BarBuilder::new()
-        .foreground_color(Color::new(158, 158, 158, 255))
-        .background_image(bg_img.clone())
-        .text_yoffset(-1)
-        .output(output)
-        .height(32)
-        .font(FONT)
-        .name(NAME)
-        .spawn()
-        .unwrap();
+    // Connect to Sway instead of i3 if it is running
+    i3::detect_sway();
 
-    let image_cache = ImageCache::new(bar.clone());
+    if options.list_outputs {
+        match i3::outputs() {
+            Ok(outputs) => {
+                for output in outputs {
+                    println!("{}", output);
+                }
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Unable to list outputs: {}", e);
+                process::exit(1);
+            }
+        }
+    }
 
-    // Workspaces
-    let mut eye_three = I3::new();
-    for i in 0..5 {
-        let id = (i * 3 + ws_offset).to_string();
+    let config = match Config::load(options.config.as_ref().map(|path| path.as_path())) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
 
-        let (tx, rx) = ::std::sync::mpsc::channel();
-        eye_three.add(id.clone(), tx);
+    // Look for images in the passed directory before the one from the config
+    let assets = Assets::new(options.assets_dir.or_else(|| config.bar.assets_dir.clone()));
 
-        let ws = Workspace::new(i, image_cache.clone(), rx);
-        bar.add(ws);
-    }
+    let bg_img = match assets.image(&config.bar.background) {
+        Some(bg_img) => bg_img,
+        None => {
+            eprintln!("Unable to find background image {:?}", config.bar.background);
+            process::exit(1);
+        }
+    };
+
+    // The output is always present unless `--list-outputs` has been passed
+    let output = options.output.unwrap();
+    let bar = BarBuilder::new()
+        .foreground_color(config.bar.foreground_color())
+        .background_image(bg_img.clone())
+        .text_yoffset(config.bar.text_yoffset)
+        .output(output.clone())
+        .height(config.bar.height)
+        .font(config.bar.font.as_str())
+        .name(config.bar.name.as_str())
+        .spawn();
+    let bar = match bar {
+        Ok(bar) => bar,
+        Err(e) => {
+            eprintln!("Unable to create bar: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let image_cache = ImageCache::new(bar.clone(), assets.clone());
 
-    // Time
-    let time = Time::new(bar.clone(), image_cache.clone());
-    bar.add(time);
+    let mut layout = Layout::new(
+        bar.clone(),
+        image_cache,
+        &config,
+        output,
+        options.ws_offset,
+        options.ws_count,
+    );
+    layout.populate(&config);
 
-    // Volume
-    let vol = VolumeSlider::new(bar.clone(), image_cache.clone(), 20, 10, 150, 10);
-    bar.add(vol);
+    // Reload components and images on change
+    let config_path = options.config.or_else(config::default_path);
+    reload::watch(layout, &config, config_path, assets.dirs().to_vec());
 
     bar.start_event_loop();
 }
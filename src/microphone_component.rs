@@ -0,0 +1,104 @@
+use leechbar::{Alignment, Background, Bar, Component, Event, Foreground, MouseButton, Text, Width};
+use config::{self, MicrophoneConfig};
+use image_cache::ImageCache;
+use pulse::{self, SOURCE};
+use chan;
+
+// Level and mute state of the default source
+pub struct Microphone {
+    bar: Bar,
+    config: MicrophoneConfig,
+    image_cache: ImageCache,
+    // Volume and mute state which is currently shown
+    last_state: Option<(usize, bool)>,
+    text: Option<Text>,
+}
+
+impl Microphone {
+    pub fn new(bar: Bar, image_cache: ImageCache, config: &MicrophoneConfig) -> Self {
+        // Start pulse listening
+        pulse::init();
+
+        Self {
+            bar,
+            image_cache,
+            config: config.clone(),
+            last_state: None,
+            text: None,
+        }
+    }
+}
+
+impl Component for Microphone {
+    fn update(&mut self) -> bool {
+        let state = (SOURCE.volume(), SOURCE.muted());
+        if self.last_state == Some(state) {
+            return false;
+        }
+        self.last_state = Some(state);
+
+        let (volume, muted) = state;
+        let (content, color) = if muted {
+            (self.config.muted_format.clone(), Some(config::inactive_color()))
+        } else {
+            (self.config.format.replace("{volume}", &volume.to_string()), None)
+        };
+
+        self.text = if !content.is_empty() {
+            Some(Text::new(&self.bar, &content, None, color).unwrap())
+        } else {
+            None
+        };
+
+        true
+    }
+
+    // Redraw when the source changes
+    fn redraw_timer(&mut self) -> chan::Receiver<()> {
+        SOURCE.redraw_timer()
+    }
+
+    // Click toggles the mute state, scrolling changes the volume
+    fn event(&mut self, event: Event) -> bool {
+        if let Event::ClickEvent(ref e) = event {
+            if e.released {
+                return false;
+            }
+
+            let vol = SOURCE.volume();
+            match e.button {
+                MouseButton::Left => SOURCE.set_muted(!SOURCE.muted()),
+                MouseButton::WheelUp if vol < 100 => SOURCE.set_volume(vol + self.config.step),
+                MouseButton::WheelDown if vol > 0 => {
+                    SOURCE.set_volume(vol.saturating_sub(self.config.step))
+                }
+                _ => return false,
+            }
+            return true;
+        }
+
+        false
+    }
+
+    fn background(&self) -> Background {
+        match self.image_cache.get(self.config.background.as_str()) {
+            Some(image) => image.into(),
+            None => Background::new(),
+        }
+    }
+
+    fn foreground(&self) -> Foreground {
+        match self.text {
+            Some(ref text) => text.clone().into(),
+            None => Foreground::new(),
+        }
+    }
+
+    fn alignment(&self) -> Alignment {
+        Alignment::RIGHT
+    }
+
+    fn width(&self) -> Width {
+        Width::new().fixed(self.config.width)
+    }
+}
@@ -0,0 +1,221 @@
+// Fake i3 for tests
+//
+// Serves the i3 IPC protocol on a temporary socket. Queries are answered from
+// JSON set by the test and events are only sent when the test emits them.
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use ipc::{read_message, write_message};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::{env, fs, process};
+use std::thread;
+
+const COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+
+// Set on the message type of events
+const EVENT_MASK: u32 = 1 << 31;
+pub const WORKSPACE_EVENT: u32 = EVENT_MASK;
+pub const MODE_EVENT: u32 = EVENT_MASK | 2;
+pub const WINDOW_EVENT: u32 = EVENT_MASK | 3;
+
+pub const OUTPUT: &str = "eDP-1";
+
+static SOCKET_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// The socket is passed to i3ipc through `I3SOCK`, so only one mock can be used at a time
+lazy_static! {
+    static ref ENV_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// Replies and event subscribers, shared by all client connections
+struct Replies {
+    tree: Value,
+    workspaces: Value,
+    outputs: Value,
+    subscribers: Vec<UnixStream>,
+}
+
+pub struct MockI3 {
+    path: PathBuf,
+    listener: Option<UnixListener>,
+    replies: Arc<Mutex<Replies>>,
+    _env_lock: MutexGuard<'static, ()>,
+}
+
+impl MockI3 {
+    // Create the socket and point i3ipc at it
+    //
+    // Clients can connect right away, but don't get any replies until `serve` is called.
+    pub fn bind() -> Self {
+        // A failed test doesn't break the others
+        let env_lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let count = SOCKET_COUNT.fetch_add(1, Ordering::SeqCst);
+        let name = format!("leechbar-test-{}-{}.sock", process::id(), count);
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        env::set_var("I3SOCK", &path);
+        env::remove_var("SWAYSOCK");
+
+        let replies = Replies {
+            tree: tree(Vec::new()),
+            workspaces: json!([]),
+            outputs: json!([{
+                "name": OUTPUT,
+                "active": true,
+                "primary": true,
+                "current_workspace": null,
+                "rect": rect(),
+            }]),
+            subscribers: Vec::new(),
+        };
+
+        Self {
+            path,
+            listener: Some(listener),
+            replies: Arc::new(Mutex::new(replies)),
+            _env_lock: env_lock,
+        }
+    }
+
+    // Start answering clients
+    pub fn serve(&mut self) {
+        let listener = self.listener.take().unwrap();
+        let replies = Arc::clone(&self.replies);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+
+                let replies = Arc::clone(&replies);
+                thread::spawn(move || handle_client(stream, &replies));
+            }
+        });
+    }
+
+    pub fn set_tree(&self, tree: Value) {
+        self.replies.lock().unwrap().tree = tree;
+    }
+
+    pub fn set_workspaces(&self, workspaces: Value) {
+        self.replies.lock().unwrap().workspaces = workspaces;
+    }
+
+    // Send an event to every client which subscribed to events
+    pub fn event(&self, event_type: u32, payload: Value) {
+        let payload = payload.to_string();
+        let mut replies = self.replies.lock().unwrap();
+        replies.subscribers.retain(|subscriber| {
+            let mut subscriber = subscriber;
+            write_message(&mut subscriber, event_type, payload.as_bytes()).is_ok()
+        });
+    }
+}
+
+impl Drop for MockI3 {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn handle_client(mut stream: UnixStream, replies: &Mutex<Replies>) {
+    while let Ok((message_type, _)) = read_message(&mut stream) {
+        let reply = {
+            let mut replies = replies.lock().unwrap();
+            match message_type {
+                COMMAND => json!([{ "success": true }]),
+                GET_WORKSPACES => replies.workspaces.clone(),
+                GET_OUTPUTS => replies.outputs.clone(),
+                GET_TREE => replies.tree.clone(),
+                SUBSCRIBE => {
+                    replies.subscribers.push(stream.try_clone().unwrap());
+                    json!({ "success": true })
+                }
+                _ => json!({ "success": false }),
+            }
+        };
+
+        let reply = reply.to_string();
+        if write_message(&mut stream, message_type, reply.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+// Workspace as reported by GET_WORKSPACES, visible workspaces on the mock output
+pub fn workspace(num: i32, focused: bool, urgent: bool) -> Value {
+    json!({
+        "num": num,
+        "name": num.to_string(),
+        "visible": focused,
+        "focused": focused,
+        "urgent": urgent,
+        "rect": rect(),
+        "output": OUTPUT,
+    })
+}
+
+// Layout tree with the workspaces on the mock output and an empty scratchpad
+pub fn tree(workspaces: Vec<Value>) -> Value {
+    let content = node(2, Some("content"), "con", workspaces, false);
+    let output = node(3, Some(OUTPUT), "output", vec![content], false);
+
+    let scratchpad = node(4, Some("__i3_scratch"), "workspace", Vec::new(), false);
+    let scratch_content = node(5, Some("content"), "con", vec![scratchpad], false);
+    let internal = node(6, Some("__i3"), "output", vec![scratch_content], false);
+
+    node(1, Some("root"), "root", vec![internal, output], false)
+}
+
+pub fn workspace_node(num: i32, windows: Vec<Value>) -> Value {
+    let name = num.to_string();
+    node(100 + i64::from(num), Some(&name), "workspace", windows, false)
+}
+
+// Window with an X11 class, ids are expected to be above the ids of workspaces
+pub fn window(id: i64, class: &str, focused: bool) -> Value {
+    let mut window = node(id, Some(class), "con", Vec::new(), focused);
+    window["window"] = json!(id);
+    window["window_properties"] = json!({
+        "class": class,
+        "instance": class.to_lowercase(),
+        "title": class,
+    });
+    window
+}
+
+// Container with all the keys i3ipc expects
+fn node(id: i64, name: Option<&str>, node_type: &str, nodes: Vec<Value>, focused: bool) -> Value {
+    json!({
+        "id": id,
+        "name": name,
+        "type": node_type,
+        "border": "normal",
+        "current_border_width": 2,
+        "layout": "splith",
+        "percent": null,
+        "rect": rect(),
+        "window_rect": rect(),
+        "deco_rect": rect(),
+        "geometry": rect(),
+        "window": null,
+        "urgent": false,
+        "focused": focused,
+        "focus": [],
+        "nodes": nodes,
+        "floating_nodes": [],
+    })
+}
+
+fn rect() -> Value {
+    json!({ "x": 0, "y": 0, "width": 1920, "height": 1080 })
+}
@@ -0,0 +1,111 @@
+use config::{self, ModeConfig, ModeStyle};
+use std::sync::mpsc::Receiver;
+use i3::DEFAULT_MODE;
+use image_cache::ImageCache;
+use std::sync::{Arc, Mutex};
+use leechbar::*;
+use std::thread;
+use chan;
+
+pub struct Mode {
+    bar: Bar,
+    config: ModeConfig,
+    image_cache: ImageCache,
+    mode: Arc<Mutex<String>>,
+    last_mode: String,
+    text: Option<Text>,
+    receiver: Option<Receiver<String>>,
+}
+
+impl Mode {
+    pub fn new(bar: Bar, image_cache: ImageCache, config: &ModeConfig, rc: Receiver<String>) -> Self {
+        Self {
+            bar,
+            image_cache,
+            config: config.clone(),
+            mode: Arc::new(Mutex::new(DEFAULT_MODE.into())),
+            last_mode: DEFAULT_MODE.into(),
+            text: None,
+            receiver: Some(rc),
+        }
+    }
+
+    fn style(&self) -> Option<&ModeStyle> {
+        self.config.modes.get(&self.last_mode)
+    }
+}
+
+impl Component for Mode {
+    fn update(&mut self) -> bool {
+        let mode = self.mode.lock().unwrap().clone();
+        if mode == self.last_mode {
+            return false;
+        }
+        self.last_mode = mode;
+
+        // Hide the component in the default mode
+        self.text = if self.last_mode != DEFAULT_MODE {
+            let color = self.style()
+                .and_then(|style| style.color.as_ref())
+                .or_else(|| self.config.color.as_ref())
+                .and_then(|color| config::parse_color(color));
+            Some(Text::new(&self.bar, &self.last_mode, None, color).unwrap())
+        } else {
+            None
+        };
+
+        true
+    }
+
+    fn redraw_timer(&mut self) -> chan::Receiver<()> {
+        // Async, the slot stops receiving once the component is replaced
+        let (tx, rx) = chan::async();
+
+        let receiver = self.receiver.take().unwrap();
+        let mode = Arc::clone(&self.mode);
+        thread::spawn(move || {
+            // Stops once the i3 listeners are cleared by a reload
+            while let Ok(new_mode) = receiver.recv() {
+                *mode.lock().unwrap() = new_mode;
+                tx.send(());
+            }
+        });
+
+        rx
+    }
+
+    fn background(&self) -> Background {
+        if self.text.is_none() {
+            return Background::new();
+        }
+
+        let background = self.style()
+            .and_then(|style| style.background.as_ref())
+            .unwrap_or(&self.config.background);
+
+        match self.image_cache.get(background.as_str()) {
+            Some(image) => image.into(),
+            None => Background::new(),
+        }
+    }
+
+    fn foreground(&self) -> Foreground {
+        match self.text {
+            Some(ref text) => text.clone().into(),
+            None => Foreground::new(),
+        }
+    }
+
+    fn alignment(&self) -> Alignment {
+        Alignment::LEFT
+    }
+
+    // Take up no space in the default mode
+    fn width(&self) -> Width {
+        if self.text.is_some() {
+            Width::new().fixed(self.config.width)
+        } else {
+            Width::new().fixed(0)
+        }
+    }
+}
@@ -0,0 +1,511 @@
+// Connection to PulseAudio shared by all audio components
+//
+// The pulse callbacks have no access to any struct, so the state of the
+// default sink and source and of all recording streams is kept in globals.
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::{Mutex, Once, ONCE_INIT};
+use std::ffi::{CStr, CString};
+use libpulse_sys::*;
+use std::{cmp, mem, ptr};
+use libc;
+use chan;
+
+// Set the 100% volume
+const MAX_VOL: f64 = 65536.;
+
+// Index of sources which are not the monitor of a sink, `PA_INVALID_INDEX`
+const INVALID_INDEX: u32 = ::std::u32::MAX;
+
+// Resampler used by streams which only measure the peak level, like the meters of pavucontrol
+const PEAKS_RESAMPLER: &[u8] = b"peaks";
+
+lazy_static! {
+    // The default output and input device
+    pub static ref SINK: Device = Device::new(DeviceKind::Sink);
+    pub static ref SOURCE: Device = Device::new(DeviceKind::Source);
+    // Applications which are recording from any source
+    pub static ref RECORDING: Recording = Recording::new();
+    // Connection used for changing devices, `None` until pulse is ready
+    static ref PULSE: Mutex<Option<Pulse>> = Mutex::new(None);
+}
+
+// Only a single pulse connection is used, even when components are recreated on reload
+static PULSE_INIT: Once = ONCE_INIT;
+
+// Handles of the pulse connection, only used while the mainloop is locked
+struct Pulse {
+    mainloop: *mut pa_threaded_mainloop,
+    context: *mut pa_context,
+}
+
+unsafe impl Send for Pulse {}
+
+// Connect to pulse, only the first call has an effect
+pub fn init() {
+    PULSE_INIT.call_once(|| unsafe { start_listener() });
+}
+
+#[derive(Clone, Copy)]
+enum DeviceKind {
+    Sink,
+    Source,
+}
+
+// Volume and mute state of the default sink or source
+pub struct Device {
+    kind: DeviceKind,
+    // The volume in percent, it is kept while the device is muted
+    volume: AtomicUsize,
+    muted: AtomicBool,
+    // Name of the default device, reported by the server
+    default_name: Mutex<Option<CString>>,
+    // Name and number of channels of the device which is shown
+    current: Mutex<Option<(CString, u8)>>,
+    // This channel is used for prompting the bar to redraw, it doesn't block the pulse
+    // mainloop because the bar might be waiting for the mainloop lock
+    redraw: (chan::Sender<()>, chan::Receiver<()>),
+}
+
+impl Device {
+    fn new(kind: DeviceKind) -> Self {
+        Self {
+            kind,
+            volume: AtomicUsize::new(0),
+            muted: AtomicBool::new(false),
+            default_name: Mutex::new(None),
+            current: Mutex::new(None),
+            redraw: chan::async(),
+        }
+    }
+
+    pub fn volume(&self) -> usize {
+        self.volume.load(Relaxed)
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted.load(Relaxed)
+    }
+
+    // Receives a message whenever the volume or mute state changed
+    pub fn redraw_timer(&self) -> chan::Receiver<()> {
+        self.redraw.1.clone()
+    }
+
+    // Set the volume in percent
+    pub fn set_volume(&self, vol: usize) {
+        let vol = cmp::min(vol, 100);
+        self.volume.store(vol, Relaxed);
+
+        let kind = self.kind;
+        self.operation(|pa_context, name, channels| unsafe {
+            let mut volume: pa_cvolume = mem::zeroed();
+            let pa_volume = (vol as f64 / 100. * MAX_VOL).round() as pa_volume_t;
+            pa_cvolume_set(&mut volume, u32::from(channels), pa_volume);
+
+            let set_volume = match kind {
+                DeviceKind::Sink => pa_context_set_sink_volume_by_name,
+                DeviceKind::Source => pa_context_set_source_volume_by_name,
+            };
+            set_volume(
+                pa_context,
+                name.as_ptr(),
+                &volume,
+                Some(pa_success_callback),
+                ptr::null_mut(),
+            )
+        });
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Relaxed);
+
+        let kind = self.kind;
+        self.operation(|pa_context, name, _| unsafe {
+            let set_mute = match kind {
+                DeviceKind::Sink => pa_context_set_sink_mute_by_name,
+                DeviceKind::Source => pa_context_set_source_mute_by_name,
+            };
+            set_mute(
+                pa_context,
+                name.as_ptr(),
+                muted as i32,
+                Some(pa_success_callback),
+                ptr::null_mut(),
+            )
+        });
+    }
+
+    // Start an operation on the shown device, failures are reported by `pa_success_callback`
+    fn operation<F>(&self, operation: F)
+    where
+        F: FnOnce(*mut pa_context, &CStr, u8) -> *mut pa_operation,
+    {
+        let (name, channels) = match *self.current.lock().unwrap() {
+            Some((ref name, channels)) => (name.clone(), channels),
+            None => {
+                warn!("Unable to change pulse device: No device available");
+                return;
+            }
+        };
+
+        let pulse = PULSE.lock().unwrap();
+        let pulse = match *pulse {
+            Some(ref pulse) => pulse,
+            None => {
+                warn!("Unable to change pulse device: Not connected to pulse");
+                return;
+            }
+        };
+
+        unsafe {
+            // The context may only be used while the mainloop is locked
+            pa_threaded_mainloop_lock(pulse.mainloop);
+            let pa_operation = operation(pulse.context, &name, channels);
+            if pa_operation.is_null() {
+                error!("Unable to change pulse device: {}", pulse_error(pulse.context));
+            } else {
+                pa_operation_unref(pa_operation);
+            }
+            pa_threaded_mainloop_unlock(pulse.mainloop);
+        }
+    }
+
+    fn set_default(&self, name: *const libc::c_char) {
+        let name = if name.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(name) }.to_owned())
+        };
+        *self.default_name.lock().unwrap() = name;
+    }
+
+    // Request the state of the default device
+    unsafe fn query(&self, pa_context: *mut pa_context) {
+        let default_name = self.default_name.lock().unwrap();
+        let name = match *default_name {
+            Some(ref name) => name,
+            None => return,
+        };
+
+        let pa_operation = match self.kind {
+            DeviceKind::Sink => pa_context_get_sink_info_by_name(
+                pa_context,
+                name.as_ptr(),
+                Some(pa_sink_callback),
+                ptr::null_mut(),
+            ),
+            DeviceKind::Source => pa_context_get_source_info_by_name(
+                pa_context,
+                name.as_ptr(),
+                Some(pa_source_callback),
+                ptr::null_mut(),
+            ),
+        };
+        pa_operation_unref(pa_operation);
+    }
+
+    fn update(&self, name: &CStr, channels: u8, volume: &pa_cvolume, muted: bool) {
+        // Ignore replies which arrive after the default device changed
+        let is_default = self.default_name
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(false, |default| default.as_c_str() == name);
+        if !is_default {
+            return;
+        }
+
+        // Remember the device for volume changes
+        *self.current.lock().unwrap() = Some((name.to_owned(), channels));
+
+        // Calculate the volume percentage
+        let vol = (100. * f64::from(unsafe { pa_cvolume_avg(volume) }) / MAX_VOL).round();
+
+        self.volume.store(vol as usize, Relaxed);
+        self.muted.store(muted, Relaxed);
+        self.redraw.0.send(());
+    }
+}
+
+// Streams recording from a source, called source outputs by pulse
+pub struct Recording {
+    // Source and application of every source output by index
+    streams: Mutex<HashMap<u32, Stream>>,
+    // Sources which monitor the output of a sink, recording those doesn't use a microphone
+    monitors: Mutex<HashSet<u32>>,
+    redraw: (chan::Sender<()>, chan::Receiver<()>),
+}
+
+struct Stream {
+    source: u32,
+    application: String,
+}
+
+impl Recording {
+    fn new() -> Self {
+        Self {
+            streams: Mutex::new(HashMap::new()),
+            monitors: Mutex::new(HashSet::new()),
+            redraw: chan::async(),
+        }
+    }
+
+    // Sorted names of the applications which are recording from a microphone
+    pub fn applications(&self) -> Vec<String> {
+        let monitors = self.monitors.lock().unwrap();
+        let mut applications: Vec<String> = self.streams
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|stream| !monitors.contains(&stream.source))
+            .map(|stream| stream.application.clone())
+            .collect();
+        applications.sort();
+        applications.dedup();
+        applications
+    }
+
+    // Receives a message whenever a stream started or stopped recording
+    pub fn redraw_timer(&self) -> chan::Receiver<()> {
+        self.redraw.1.clone()
+    }
+
+    fn set_stream(&self, index: u32, stream: Option<Stream>) {
+        {
+            let mut streams = self.streams.lock().unwrap();
+            match stream {
+                Some(stream) => streams.insert(index, stream),
+                None => streams.remove(&index),
+            };
+        }
+        self.redraw.0.send(());
+    }
+
+    fn set_monitor(&self, source: u32, monitor: bool) {
+        let changed = {
+            let mut monitors = self.monitors.lock().unwrap();
+            if monitor {
+                monitors.insert(source)
+            } else {
+                monitors.remove(&source)
+            }
+        };
+
+        if changed {
+            self.redraw.0.send(());
+        }
+    }
+}
+
+// Description of the last error of the context
+unsafe fn pulse_error(pa_context: *mut pa_context) -> String {
+    CStr::from_ptr(pa_strerror(pa_context_errno(pa_context)))
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Start the pulseaudio listener
+unsafe fn start_listener() {
+    // Start the async main loop
+    let pa_mainloop = pa_threaded_mainloop_new();
+    pa_threaded_mainloop_start(pa_mainloop);
+
+    // Create a pulseaudio context
+    let pa_mainloop_api = pa_threaded_mainloop_get_api(pa_mainloop);
+    let pa_context = pa_context_new(pa_mainloop_api, ptr::null());
+
+    // Register the callback for successful context connection
+    pa_threaded_mainloop_lock(pa_mainloop);
+    pa_context_set_state_callback(pa_context, Some(pa_context_callback), ptr::null_mut());
+    pa_context_connect(pa_context, ptr::null(), PA_CONTEXT_NOFLAGS, ptr::null());
+    pa_threaded_mainloop_unlock(pa_mainloop);
+
+    *PULSE.lock().unwrap() = Some(Pulse {
+        mainloop: pa_mainloop,
+        context: pa_context,
+    });
+}
+
+// Callback when pulseaudio context connected
+unsafe extern "C" fn pa_context_callback(pa_context: *mut pa_context, _: *mut libc::c_void) {
+    // Check the context state
+    match pa_context_get_state(pa_context) {
+        // Ignore these states
+        PA_CONTEXT_CONNECTING | PA_CONTEXT_AUTHORIZING | PA_CONTEXT_SETTING_NAME => (),
+        // If the state is ready, we can subscribe to pulse events
+        PA_CONTEXT_READY => {
+            // Setup the callback for the subscriptyon
+            pa_context_set_subscribe_callback(
+                pa_context,
+                Some(pa_subscription_callback),
+                ptr::null_mut(),
+            );
+
+            // Subscribe to device events, server events for changes of the default devices
+            // and source output events for applications which start or stop recording
+            let mask = PA_SUBSCRIPTION_MASK_SINK | PA_SUBSCRIPTION_MASK_SOURCE
+                | PA_SUBSCRIPTION_MASK_SOURCE_OUTPUT | PA_SUBSCRIPTION_MASK_SERVER;
+            let pa_operation = pa_context_subscribe(pa_context, mask, None, ptr::null_mut());
+            pa_operation_unref(pa_operation);
+
+            // Get the initial state, the sources are requested first so the monitors are
+            // known before the recording streams
+            query_server(pa_context);
+            let pa_operation = pa_context_get_source_info_list(
+                pa_context,
+                Some(pa_source_callback),
+                ptr::null_mut(),
+            );
+            pa_operation_unref(pa_operation);
+            let pa_operation = pa_context_get_source_output_info_list(
+                pa_context,
+                Some(pa_source_output_callback),
+                ptr::null_mut(),
+            );
+            pa_operation_unref(pa_operation);
+        }
+        _ => {
+            // Abort if connection to pulse was not possible
+            let error = pa_strerror(pa_context_errno(pa_context));
+            pa_context_unref(pa_context);
+            panic!("Pulse connection failure: {:?}", error);
+        }
+    };
+}
+
+// Result of a volume or mute change
+unsafe extern "C" fn pa_success_callback(
+    pa_context: *mut pa_context,
+    success: i32,
+    _: *mut libc::c_void,
+) {
+    if success == 0 {
+        error!("Unable to change pulse device: {}", pulse_error(pa_context));
+    }
+}
+
+// Device and server event callback
+unsafe extern "C" fn pa_subscription_callback(
+    pa_context: *mut pa_context,
+    event: Enum_pa_subscription_event_type,
+    index: u32,
+    _: *mut libc::c_void,
+) {
+    let removed = event & PA_SUBSCRIPTION_EVENT_TYPE_MASK == PA_SUBSCRIPTION_EVENT_REMOVE;
+    match event & PA_SUBSCRIPTION_EVENT_FACILITY_MASK {
+        // The default devices might have changed
+        PA_SUBSCRIPTION_EVENT_SERVER => query_server(pa_context),
+        PA_SUBSCRIPTION_EVENT_SINK => SINK.query(pa_context),
+        PA_SUBSCRIPTION_EVENT_SOURCE if removed => RECORDING.set_monitor(index, false),
+        PA_SUBSCRIPTION_EVENT_SOURCE => {
+            let pa_operation = pa_context_get_source_info_by_index(
+                pa_context,
+                index,
+                Some(pa_source_callback),
+                ptr::null_mut(),
+            );
+            pa_operation_unref(pa_operation);
+        }
+        PA_SUBSCRIPTION_EVENT_SOURCE_OUTPUT if removed => RECORDING.set_stream(index, None),
+        PA_SUBSCRIPTION_EVENT_SOURCE_OUTPUT => {
+            let pa_operation = pa_context_get_source_output_info(
+                pa_context,
+                index,
+                Some(pa_source_output_callback),
+                ptr::null_mut(),
+            );
+            pa_operation_unref(pa_operation);
+        }
+        _ => (),
+    }
+}
+
+unsafe fn query_server(pa_context: *mut pa_context) {
+    let pa_operation =
+        pa_context_get_server_info(pa_context, Some(pa_server_callback), ptr::null_mut());
+    pa_operation_unref(pa_operation);
+}
+
+// Remember the default devices and get their state
+unsafe extern "C" fn pa_server_callback(
+    pa_context: *mut pa_context,
+    pa_server_info: *const Struct_pa_server_info,
+    _: *mut libc::c_void,
+) {
+    if pa_server_info.is_null() {
+        return;
+    }
+
+    SINK.set_default((*pa_server_info).default_sink_name);
+    SOURCE.set_default((*pa_server_info).default_source_name);
+    SINK.query(pa_context);
+    SOURCE.query(pa_context);
+}
+
+unsafe extern "C" fn pa_sink_callback(
+    _: *mut Struct_pa_context,
+    pa_sink_info: *const Struct_pa_sink_info,
+    _: i32,
+    _: *mut libc::c_void,
+) {
+    if !pa_sink_info.is_null() {
+        let info = &*pa_sink_info;
+        let name = CStr::from_ptr(info.name);
+        SINK.update(name, info.channel_map.channels, &info.volume, info.mute == 1);
+    }
+}
+
+unsafe extern "C" fn pa_source_callback(
+    _: *mut Struct_pa_context,
+    pa_source_info: *const Struct_pa_source_info,
+    _: i32,
+    _: *mut libc::c_void,
+) {
+    if !pa_source_info.is_null() {
+        let info = &*pa_source_info;
+        RECORDING.set_monitor(info.index, info.monitor_of_sink != INVALID_INDEX);
+
+        let name = CStr::from_ptr(info.name);
+        SOURCE.update(name, info.channel_map.channels, &info.volume, info.mute == 1);
+    }
+}
+
+// Remember which application a source output belongs to
+unsafe extern "C" fn pa_source_output_callback(
+    _: *mut Struct_pa_context,
+    pa_source_output_info: *const Struct_pa_source_output_info,
+    _: i32,
+    _: *mut libc::c_void,
+) {
+    if pa_source_output_info.is_null() {
+        return;
+    }
+    let info = &*pa_source_output_info;
+
+    // Level meters don't record anything and paused streams aren't recording right now
+    let peak_detect = !info.resample_method.is_null()
+        && CStr::from_ptr(info.resample_method).to_bytes() == PEAKS_RESAMPLER;
+    if peak_detect || info.corked != 0 {
+        RECORDING.set_stream(info.index, None);
+        return;
+    }
+
+    // Fall back to the binary and the stream name for clients without an application name
+    let keys: [&[u8]; 2] = [b"application.name\0", b"application.process.binary\0"];
+    let application = keys.iter()
+        .map(|key| pa_proplist_gets(info.proplist, key.as_ptr() as *const libc::c_char))
+        .chain(Some(info.name))
+        .find(|value| !value.is_null())
+        .map_or_else(
+            || "unknown".into(),
+            |value| CStr::from_ptr(value).to_string_lossy().into_owned(),
+        );
+
+    let stream = Stream {
+        source: info.source,
+        application,
+    };
+    RECORDING.set_stream(info.index, Some(stream));
+}
@@ -0,0 +1,139 @@
+use leechbar::{Alignment, Background, Bar, Component, Event, Foreground, MouseButton, Text, Width};
+use config::{self, RecordingConfig};
+use image_cache::ImageCache;
+use pulse::{self, RECORDING};
+use chan;
+
+// Indicator which lights up while any application is recording from a microphone
+pub struct Recording {
+    bar: Bar,
+    config: RecordingConfig,
+    image_cache: ImageCache,
+    // Height of the bar, used for noticing when the pointer leaves the component
+    height: i16,
+    // The application list is shown while hovering or while pinned by a click
+    hovering: bool,
+    clicked: bool,
+    // Recording applications and whether the list was expanded when the text was created
+    last_state: Option<(Vec<String>, bool)>,
+    text: Option<Text>,
+}
+
+impl Recording {
+    pub fn new(bar: Bar, image_cache: ImageCache, config: &RecordingConfig, height: u16) -> Self {
+        // Start pulse listening
+        pulse::init();
+
+        Self {
+            bar,
+            image_cache,
+            config: config.clone(),
+            height: height as i16,
+            hovering: false,
+            clicked: false,
+            last_state: None,
+            text: None,
+        }
+    }
+
+    fn expanded(&self) -> bool {
+        self.hovering || self.clicked
+    }
+
+    fn recording(&self) -> bool {
+        self.last_state
+            .as_ref()
+            .map_or(false, |&(ref applications, _)| !applications.is_empty())
+    }
+
+    fn current_width(&self) -> u16 {
+        if self.expanded() {
+            self.config.list_width
+        } else {
+            self.config.width
+        }
+    }
+}
+
+impl Component for Recording {
+    fn update(&mut self) -> bool {
+        let state = (RECORDING.applications(), self.expanded());
+        if self.last_state.as_ref() == Some(&state) {
+            return false;
+        }
+
+        let content = match state {
+            (ref applications, true) if applications.is_empty() => self.config.idle_list.clone(),
+            (ref applications, true) => applications.join(", "),
+            _ => self.config.label.clone(),
+        };
+        let color = if state.0.is_empty() {
+            Some(config::inactive_color())
+        } else {
+            config::parse_color(&self.config.color)
+        };
+
+        self.text = if !content.is_empty() {
+            Some(Text::new(&self.bar, &content, None, color).unwrap())
+        } else {
+            None
+        };
+        self.last_state = Some(state);
+
+        true
+    }
+
+    // Redraw when an application starts or stops recording
+    fn redraw_timer(&mut self) -> chan::Receiver<()> {
+        RECORDING.redraw_timer()
+    }
+
+    // Clicking pins the application list, hovering shows it until the pointer leaves
+    fn event(&mut self, event: Event) -> bool {
+        match event {
+            Event::ClickEvent(ref e) => if e.button == MouseButton::Left && !e.released {
+                self.clicked = !self.clicked;
+                return true;
+            },
+            Event::MotionEvent(ref e) => {
+                let pos = e.position;
+                let leaving = pos.x <= 0 || pos.y <= 0 || pos.x >= self.current_width() as i16 - 1
+                    || pos.y >= self.height - 1;
+                if leaving == self.hovering {
+                    self.hovering = !leaving;
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    fn background(&self) -> Background {
+        let background = if self.recording() {
+            &self.config.active_background
+        } else {
+            &self.config.background
+        };
+
+        match self.image_cache.get(background.as_str()) {
+            Some(image) => image.into(),
+            None => Background::new(),
+        }
+    }
+
+    fn foreground(&self) -> Foreground {
+        match self.text {
+            Some(ref text) => text.clone().into(),
+            None => Foreground::new(),
+        }
+    }
+
+    fn alignment(&self) -> Alignment {
+        Alignment::CENTER
+    }
+
+    fn width(&self) -> Width {
+        Width::new().fixed(self.current_width())
+    }
+}
@@ -0,0 +1,131 @@
+use notify::{self, DebouncedEvent, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::sync::mpsc;
+use layout::Layout;
+use config::{ComponentConfig, Config};
+use std::thread;
+use assets;
+
+// Time to wait for more file changes before reloading
+const DEBOUNCE_MS: u64 = 250;
+
+// Watch the config file and asset directories and reload the bar on change
+pub fn watch(
+    mut layout: Layout,
+    config: &Config,
+    config_path: Option<PathBuf>,
+    asset_dirs: Vec<PathBuf>,
+) {
+    let mut rules = rules_paths(config);
+
+    thread::spawn(move || {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = match notify::watcher(tx, Duration::from_millis(DEBOUNCE_MS)) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                error!("Unable to start file watcher: {}", e);
+                return;
+            }
+        };
+
+        // Watch the config directory, editors often replace the file instead of writing to it
+        let config_path = config_path.map(|path| assets::absolute(&path));
+        if let Some(dir) = config_path.as_ref().and_then(|path| path.parent()) {
+            if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                warn!("Unable to watch config directory {:?}: {}", dir, e);
+            }
+        }
+
+        for dir in asset_dirs.iter().filter(|dir| dir.is_dir()) {
+            if let Err(e) = watcher.watch(dir, RecursiveMode::Recursive) {
+                warn!("Unable to watch asset directory {:?}: {}", dir, e);
+            }
+        }
+
+        watch_rules(&mut watcher, &rules);
+
+        while let Ok(event) = rx.recv() {
+            let path = match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Remove(path)
+                | DebouncedEvent::Rename(_, path) => assets::absolute(&path),
+                DebouncedEvent::Error(e, _) => {
+                    warn!("File watcher error: {}", e);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            // Rules are loaded together with the components
+            let reload_path = if Some(&path) == config_path.as_ref() {
+                Some(path.clone())
+            } else if rules.contains(&path) || is_rules(&path, config_path.as_ref()) {
+                config_path.clone()
+            } else {
+                None
+            };
+
+            if let Some(reload_path) = reload_path {
+                if let Some(config) = reload_config(&mut layout, &reload_path) {
+                    rules = rules_paths(&config);
+                    watch_rules(&mut watcher, &rules);
+                }
+            } else if is_image(&path) {
+                layout.reload_image(&path);
+            }
+        }
+    });
+}
+
+fn reload_config(layout: &mut Layout, path: &Path) -> Option<Config> {
+    if !path.exists() {
+        return None;
+    }
+
+    match Config::load(Some(path)) {
+        Ok(config) => {
+            info!("Reloading config");
+            layout.reload(&config);
+            Some(config)
+        }
+        // Keep the old layout running when the new config is invalid
+        Err(e) => {
+            error!("{}", e);
+            None
+        }
+    }
+}
+
+// Rules files set in the config, these can be outside of the config directory
+fn rules_paths(config: &Config) -> Vec<PathBuf> {
+    config
+        .components
+        .iter()
+        .filter_map(|component| match *component {
+            ComponentConfig::Workspaces(ref ws_config) => ws_config.rules.as_ref(),
+            _ => None,
+        })
+        .map(|path| assets::absolute(path))
+        .collect()
+}
+
+// Watching a directory again has no effect, so this is called after every reload
+fn watch_rules<W: Watcher>(watcher: &mut W, rules: &[PathBuf]) {
+    for dir in rules.iter().filter_map(|path| path.parent()) {
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            warn!("Unable to watch rules directory {:?}: {}", dir, e);
+        }
+    }
+}
+
+// Icon rules are stored next to the config file
+fn is_rules(path: &Path, config_path: Option<&PathBuf>) -> bool {
+    let config_dir = config_path.and_then(|config_path| config_path.parent());
+    path.extension().map_or(false, |ext| ext == "toml") && path.parent() == config_dir
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "png")
+}
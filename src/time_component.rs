@@ -1,5 +1,8 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use image_cache::ImageCache;
+use config::TimeConfig;
 use std::time::Duration;
+use std::sync::Arc;
 use leechbar::*;
 use std::thread;
 use time;
@@ -7,26 +10,40 @@ use chan;
 
 pub struct Time {
     bar: Bar,
+    format: String,
+    interval: u64,
+    width: u16,
     image_cache: ImageCache,
     last_content: String,
     last_text: Option<Text>,
+    // Cleared when the component is dropped, which stops the timer
+    running: Arc<AtomicBool>,
 }
 
 impl Time {
-    pub fn new(bar: Bar, image_cache: ImageCache) -> Self {
+    pub fn new(bar: Bar, image_cache: ImageCache, config: &TimeConfig) -> Self {
         Self {
             bar,
             image_cache,
+            format: config.format.clone(),
+            interval: config.interval,
+            width: config.width,
             last_text: None,
             last_content: String::new(),
+            running: Arc::new(AtomicBool::new(true)),
         }
     }
 }
 
+impl Drop for Time {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
 impl Component for Time {
     fn update(&mut self) -> bool {
-        let time = time::now();
-        let content = format!("{:02}:{:02}", time.tm_hour, time.tm_min);
+        let content = time::strftime(&self.format, &time::now()).unwrap_or_default();
 
         if content != self.last_content {
             self.last_text = if !content.is_empty() {
@@ -43,7 +60,7 @@ impl Component for Time {
     }
 
     fn background(&self) -> Background {
-        Background::new().image(self.image_cache.get("./images/bg_sec.png").unwrap())
+        Background::new().image(self.image_cache.get("bg_sec.png").unwrap())
     }
 
     fn foreground(&self) -> Foreground {
@@ -55,11 +72,14 @@ impl Component for Time {
     }
 
     fn redraw_timer(&mut self) -> chan::Receiver<()> {
-        let (tx, rx) = chan::sync(0);
+        // Async, the slot stops receiving once the component is replaced
+        let (tx, rx) = chan::async();
 
+        let interval = self.interval;
+        let running = Arc::clone(&self.running);
         thread::spawn(move || {
-            loop {
-                thread::sleep(Duration::from_secs(15));
+            while running.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_secs(interval));
                 tx.send(());
             }
         });
@@ -68,6 +88,6 @@ impl Component for Time {
     }
 
     fn width(&self) -> Width {
-        Width::new().fixed(100)
+        Width::new().fixed(self.width)
     }
 }
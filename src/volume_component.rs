@@ -72,7 +72,7 @@ impl Component for Volume {
 
     // Draw the background
     fn background(&self) -> Background {
-        Background::new().image(self.cache.get("./images/bg_sec.png").unwrap())
+        Background::new().image(self.cache.get("bg_sec.png").unwrap())
     }
 
     // Fixed right alignment
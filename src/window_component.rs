@@ -0,0 +1,162 @@
+use config::{TitleSource, WindowTitleConfig};
+use std::sync::mpsc::Receiver;
+use image_cache::ImageCache;
+use std::sync::{Arc, Mutex};
+use pango::{FontDescription, LayoutExt};
+use i3::FocusedWindow;
+use leechbar::*;
+use std::thread;
+use pangocairo::CairoContextExt;
+use cairo;
+use chan;
+
+// Space between the title and the borders of the component
+const PADDING: u16 = 10;
+const ELLIPSIS: &str = "…";
+
+pub struct WindowTitle {
+    bar: Bar,
+    font: String,
+    config: WindowTitleConfig,
+    image_cache: ImageCache,
+    window: Arc<Mutex<Option<FocusedWindow>>>,
+    last_content: String,
+    text: Option<Text>,
+    receiver: Option<Receiver<Option<FocusedWindow>>>,
+}
+
+impl WindowTitle {
+    pub fn new(
+        bar: Bar,
+        image_cache: ImageCache,
+        font: String,
+        config: &WindowTitleConfig,
+        rc: Receiver<Option<FocusedWindow>>,
+    ) -> Self {
+        Self {
+            bar,
+            font,
+            image_cache,
+            config: config.clone(),
+            window: Arc::new(Mutex::new(None)),
+            last_content: String::new(),
+            text: None,
+            receiver: Some(rc),
+        }
+    }
+}
+
+impl Component for WindowTitle {
+    fn update(&mut self) -> bool {
+        let content = match *self.window.lock().unwrap() {
+            Some(ref window) => match self.config.show {
+                TitleSource::Class => window.class.clone().unwrap_or_default(),
+                TitleSource::Title => window.title.clone(),
+            },
+            None => String::new(),
+        };
+
+        if content == self.last_content {
+            return false;
+        }
+
+        let max_width = i32::from(self.config.width.saturating_sub(2 * PADDING));
+        let truncated = truncate_middle(&content, &self.font, max_width);
+        self.text = if !truncated.is_empty() {
+            Some(Text::new(&self.bar, &truncated, None, None).unwrap())
+        } else {
+            None
+        };
+        self.last_content = content;
+
+        true
+    }
+
+    fn redraw_timer(&mut self) -> chan::Receiver<()> {
+        // Async, the slot stops receiving once the component is replaced
+        let (tx, rx) = chan::async();
+
+        let receiver = self.receiver.take().unwrap();
+        let window = Arc::clone(&self.window);
+        thread::spawn(move || {
+            // Stops once the i3 listeners are cleared by a reload
+            while let Ok(new_window) = receiver.recv() {
+                *window.lock().unwrap() = new_window;
+                tx.send(());
+            }
+        });
+
+        rx
+    }
+
+    fn background(&self) -> Background {
+        match self.image_cache.get(self.config.background.as_str()) {
+            Some(image) => image.into(),
+            None => Background::new(),
+        }
+    }
+
+    fn foreground(&self) -> Foreground {
+        match self.text {
+            Some(ref text) => text.clone().into(),
+            None => Foreground::new(),
+        }
+    }
+
+    fn alignment(&self) -> Alignment {
+        Alignment::CENTER
+    }
+
+    fn width(&self) -> Width {
+        Width::new().fixed(self.config.width)
+    }
+}
+
+// Replace the middle of the text with an ellipsis until it fits into `max_width` pixels
+fn truncate_middle(text: &str, font: &str, max_width: i32) -> String {
+    if text_width(text, font) <= max_width {
+        return text.to_owned();
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+    let shortened = |keep: usize| {
+        let front: String = chars[..(keep + 1) / 2].iter().collect();
+        let back: String = chars[chars.len() - keep / 2..].iter().collect();
+        format!("{}{}{}", front, ELLIPSIS, back)
+    };
+
+    // Binary search for the most characters which still fit
+    let (mut low, mut high) = (0, chars.len() - 1);
+    while low < high {
+        let mid = (low + high + 1) / 2;
+        if text_width(&shortened(mid), font) <= max_width {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+
+    if low == 0 && text_width(ELLIPSIS, font) > max_width {
+        String::new()
+    } else {
+        shortened(low)
+    }
+}
+
+// Width of the text in pixels when rendered with the font
+fn text_width(text: &str, font: &str) -> i32 {
+    let surface = match cairo::ImageSurface::create(cairo::Format::ARgb32, 1, 1) {
+        Ok(surface) => surface,
+        Err(e) => {
+            error!("Unable to measure window title: {:?}", e);
+            return 0;
+        }
+    };
+    let context = cairo::Context::new(&surface);
+    let layout = context.create_pango_layout();
+
+    layout.set_font_description(Some(&FontDescription::from_string(font)));
+    layout.set_text(text);
+
+    layout.get_pixel_size().0
+}
@@ -1,58 +1,244 @@
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Receiver;
+use i3::{AppCount, I3Change, I3Commander, WorkspaceStatus};
+use config::{self, Action, Badge, WorkspaceActions, WorkspacesConfig};
 use image_cache::ImageCache;
 use std::sync::{Arc, Mutex};
 use std::process::Command;
-use std::path::Path;
-use i3::I3Change;
+use std::path::PathBuf;
 use leechbar::*;
 use std::thread;
 use chan;
 
+// Color blended over the workspace image if there is no urgent image
+const URGENT_TINT: [u8; 4] = [117, 42, 42, 128];
+// Color blended over the focused image for workspaces shown on an unfocused output
+const VISIBLE_TINT: [u8; 4] = [0, 0, 0, 96];
+// Color blended over the workspace image while i3 is unavailable
+const DISCONNECTED_TINT: [u8; 4] = [40, 40, 40, 160];
+
+// State of a workspace button which is shared with the other buttons of the strip
+#[derive(Clone)]
+pub struct ButtonState {
+    name: Arc<Mutex<Option<String>>>,
+    status: Arc<Mutex<WorkspaceStatus>>,
+    title: Arc<Mutex<String>>,
+}
+
+// All buttons of one workspace component, in order
+pub type Strip = Arc<Mutex<Vec<ButtonState>>>;
+
 pub struct Workspace {
-    id: i32,
+    bar: Bar,
+    name: Arc<Mutex<Option<String>>>,
+    width: u16,
+    actions: WorkspaceActions,
+    wrap: bool,
+    skip_empty: bool,
+    badge: Badge,
+    max_icons: usize,
+    badge_color: Option<Color>,
+    strip: Strip,
+    i3: I3Commander,
     image_cache: ImageCache,
-    visible: Arc<AtomicBool>,
+    status: Arc<Mutex<WorkspaceStatus>>,
+    urgent: Arc<AtomicBool>,
+    connected: Arc<AtomicBool>,
     title: Arc<Mutex<String>>,
+    apps: Arc<Mutex<Vec<AppCount>>>,
+    last_windows: usize,
+    text: Option<Text>,
     receiver: Option<Receiver<I3Change>>,
 }
 
 impl Workspace {
-    pub fn new(id: i32, image_cache: ImageCache, rc: Receiver<I3Change>) -> Self {
-        Self {
-            id: id + 1,
+    // Create a workspace button, buttons without a name are hidden until i3 assigns one
+    pub fn new(
+        bar: Bar,
+        name: Option<String>,
+        config: &WorkspacesConfig,
+        image_cache: ImageCache,
+        i3: I3Commander,
+        strip: Strip,
+        rc: Receiver<I3Change>,
+    ) -> Self {
+        let workspace = Self {
+            bar,
+            i3,
             image_cache,
+            name: Arc::new(Mutex::new(name)),
+            width: config.width,
+            actions: config.actions.clone(),
+            wrap: config.wrap,
+            skip_empty: config.skip_empty,
+            badge: config.badge,
+            max_icons: config.max_icons,
+            badge_color: config.badge_color.as_ref().and_then(|c| config::parse_color(c)),
+            strip,
             receiver: Some(rc),
             title: Arc::new(Mutex::new("empty".into())),
-            visible: Arc::new(AtomicBool::new(true)),
+            apps: Arc::new(Mutex::new(Vec::new())),
+            last_windows: 0,
+            text: None,
+            status: Arc::new(Mutex::new(WorkspaceStatus::Hidden)),
+            urgent: Arc::new(AtomicBool::new(false)),
+            connected: Arc::new(AtomicBool::new(true)),
+        };
+
+        workspace.strip.lock().unwrap().push(ButtonState {
+            name: Arc::clone(&workspace.name),
+            status: Arc::clone(&workspace.status),
+            title: Arc::clone(&workspace.title),
+        });
+
+        workspace
+    }
+
+    fn switch_to(&self, name: &str) {
+        match self.actions.left {
+            Some(ref action) => self.run(action, name),
+            None => self.i3.run(&format!("workspace {}", i3_quote(name))),
+        }
+    }
+
+    fn run(&self, action: &Action, workspace: &str) {
+        let name = self.title.lock().unwrap().clone();
+
+        if let Some(ref command) = action.i3 {
+            self.i3.run(&expand(command, workspace, &name, i3_quote));
+        }
+
+        if let Some(ref command) = action.shell {
+            // Run in the background, the command might wait for user input
+            let command = expand(command, workspace, &name, shell_quote);
+            thread::spawn(move || {
+                let _ = Command::new("sh").args(&["-c", &command]).output();
+            });
         }
     }
+
+    // Name of the workspace `offset` buttons away from the current workspace of this output
+    fn cycle(&self, offset: isize) -> Option<String> {
+        let strip = self.strip.lock().unwrap();
+
+        // Skip hidden buttons and empty workspaces if requested, the current workspace is
+        // kept even when it is empty so there is a position to start from
+        let buttons: Vec<(String, WorkspaceStatus)> = strip
+            .iter()
+            .filter_map(|button| {
+                let name = button.name.lock().unwrap().clone()?;
+                Some((name, *button.status.lock().unwrap(), button))
+            })
+            .filter(|&(_, status, button)| {
+                !self.skip_empty || status != WorkspaceStatus::Hidden
+                    || *button.title.lock().unwrap() != "empty"
+            })
+            .map(|(name, status, _)| (name, status))
+            .collect();
+
+        // Use the visible workspace if another output has focus
+        let current = buttons
+            .iter()
+            .position(|&(_, status)| status == WorkspaceStatus::Focused)
+            .or_else(|| {
+                buttons
+                    .iter()
+                    .position(|&(_, status)| status == WorkspaceStatus::Visible)
+            })?;
+
+        let len = buttons.len() as isize;
+        let mut target = current as isize + offset;
+        if self.wrap {
+            target = (target % len + len) % len;
+        } else if target < 0 || target >= len {
+            return None;
+        }
+
+        Some(buttons[target as usize].0.clone())
+    }
+
+    // Background with small icons of the applications, if there are several
+    fn stacked(&self, suffix: &str) -> Option<Image> {
+        if self.badge != Badge::Icons {
+            return None;
+        }
+
+        let apps = self.apps.lock().unwrap();
+        if apps.len() < 2 {
+            return None;
+        }
+
+        let icons: Vec<PathBuf> = apps.iter()
+            .take(self.max_icons)
+            .map(|app| format!("ws/{}.png", app.name).into())
+            .collect();
+        self.image_cache
+            .get_stacked(format!("ws/mixed{}.png", suffix), &icons)
+    }
 }
 
 impl Component for Workspace {
+    // Redraws are only requested when the workspace changed
+    fn update(&mut self) -> bool {
+        if self.badge == Badge::Count {
+            let windows: usize = self.apps.lock().unwrap().iter().map(|app| app.windows).sum();
+            if windows != self.last_windows {
+                self.last_windows = windows;
+                self.text = if windows > 1 {
+                    let count = windows.to_string();
+                    Some(Text::new(&self.bar, &count, None, self.badge_color).unwrap())
+                } else {
+                    None
+                };
+            }
+        }
+
+        true
+    }
+
     fn redraw_timer(&mut self) -> chan::Receiver<()> {
-        let (tx, rx) = chan::sync(0);
+        // Async, the slot stops receiving once the component is replaced
+        let (tx, rx) = chan::async();
 
         let receiver = self.receiver.take().unwrap();
-        let visible = Arc::clone(&self.visible);
+        let status = Arc::clone(&self.status);
+        let urgent = Arc::clone(&self.urgent);
+        let connected = Arc::clone(&self.connected);
         let title = Arc::clone(&self.title);
+        let apps = Arc::clone(&self.apps);
+        let name = Arc::clone(&self.name);
         thread::spawn(move || {
-            loop {
-                if let Ok(change) = receiver.recv() {
-                    if let Some(new_visible) = change.state {
-                        if visible.load(Ordering::Relaxed) != new_visible {
-                            visible.store(new_visible, Ordering::Relaxed);
-                            tx.send(());
-                        }
-                    }
-
-                    let mut title_lock = title.lock().unwrap();
-                    if let Some(new_title) = change.title {
-                        if *title_lock != new_title {
-                            *title_lock = new_title;
-                            tx.send(());
-                        }
-                    }
+            // Stops once the i3 listeners are cleared by a reload
+            while let Ok(change) = receiver.recv() {
+                let mut changed = false;
+
+                if let Some(new_name) = change.workspace {
+                    changed |= update(&name, new_name);
+                }
+
+                if let Some(new_status) = change.state {
+                    changed |= update(&status, new_status);
+                }
+
+                if let Some(new_urgent) = change.urgent {
+                    changed |= urgent.swap(new_urgent, Ordering::Relaxed) != new_urgent;
+                }
+
+                if let Some(new_connected) = change.connected {
+                    changed |= connected.swap(new_connected, Ordering::Relaxed) != new_connected;
+                }
+
+                if let Some(new_title) = change.title {
+                    changed |= update(&title, new_title);
+                }
+
+                if let Some(new_apps) = change.apps {
+                    changed |= update(&apps, new_apps);
+                }
+
+                // All locks are released, the bar might need them for redrawing
+                if changed {
+                    tx.send(());
                 }
             }
         });
@@ -62,10 +248,31 @@ impl Component for Workspace {
 
     fn event(&mut self, event: Event) -> bool {
         if let Event::ClickEvent(e) = event {
-            if let MouseButton::Left = e.button {
-                // Change workspace and swallow stdout
-                let command = format!("$HOME/scripts/switch_focused_workspace {}", self.id);
-                let _ = Command::new("sh").args(&["-c", &command]).output();
+            // Every click is reported on press and on release, only act once
+            if e.released {
+                return false;
+            }
+
+            // Hidden buttons don't have a workspace to act on
+            let name = match self.name.lock().unwrap().clone() {
+                Some(name) => name,
+                None => return false,
+            };
+
+            match e.button {
+                MouseButton::Left => self.switch_to(&name),
+                MouseButton::Middle => if let Some(ref action) = self.actions.middle {
+                    self.run(action, &name);
+                },
+                MouseButton::Right => if let Some(ref action) = self.actions.right {
+                    self.run(action, &name);
+                },
+                MouseButton::WheelUp => if let Some(target) = self.cycle(-1) {
+                    self.switch_to(&target);
+                },
+                MouseButton::WheelDown => if let Some(target) = self.cycle(1) {
+                    self.switch_to(&target);
+                },
             }
         }
 
@@ -73,40 +280,122 @@ impl Component for Workspace {
     }
 
     fn background(&self) -> Background {
+        if self.name.lock().unwrap().is_none() {
+            return Background::new();
+        }
+
         // Lock title to this thread
         let title_lock = self.title.lock().unwrap();
 
-        let path_str;
-        let path = if !self.visible.load(Ordering::Relaxed) {
-            path_str = format!("./images/ws/{}.png", title_lock);
-            let path = Path::new(&path_str);
-            if path.exists() {
-                path
-            } else {
-                Path::new("./images/ws/mixed.png")
+        let status = *self.status.lock().unwrap();
+
+        // The state might be outdated until i3 is back
+        if !self.connected.load(Ordering::Relaxed) {
+            let disconnected_image = self.image_cache
+                .get("ws/disconnected.png")
+                .or_else(|| {
+                    let name = format!("ws/{}.png", title_lock);
+                    self.image_cache.get_tinted(name, DISCONNECTED_TINT)
+                })
+                .or_else(|| self.image_cache.get_tinted("ws/mixed.png", DISCONNECTED_TINT));
+
+            if let Some(image) = disconnected_image {
+                return image.into();
             }
-        } else {
-            path_str = format!("./images/ws/{}_sec.png", title_lock);
-            let path = Path::new(&path_str);
-            if path.exists() {
-                path
-            } else {
-                Path::new("./images/ws/mixed_sec.png")
+        }
+
+        // Urgency is cleared once the workspace is focused
+        if self.urgent.load(Ordering::Relaxed) && status != WorkspaceStatus::Focused {
+            let urgent_image = self.image_cache
+                .get(format!("ws/{}_urgent.png", title_lock))
+                .or_else(|| {
+                    let name = format!("ws/{}.png", title_lock);
+                    self.image_cache.get_tinted(name, URGENT_TINT)
+                })
+                .or_else(|| self.image_cache.get_tinted("ws/mixed.png", URGENT_TINT));
+
+            if let Some(image) = urgent_image {
+                return image.into();
             }
+        }
+
+        let suffix = match status {
+            WorkspaceStatus::Focused => "_sec",
+            WorkspaceStatus::Visible => "_visible",
+            WorkspaceStatus::Hidden => "",
         };
 
-        self.image_cache.get(path).unwrap().into()
+        // Fall back to the mixed image if there is no image for this title
+        self.stacked(suffix)
+            .or_else(|| self.image_cache.get(format!("ws/{}{}.png", title_lock, suffix)))
+            .or_else(|| self.image_cache.get(format!("ws/mixed{}.png", suffix)))
+            .or_else(|| {
+                // Visible workspaces are dimmed focused ones if there are no separate images
+                if status != WorkspaceStatus::Visible {
+                    return None;
+                }
+                let name = format!("ws/{}_sec.png", title_lock);
+                self.image_cache
+                    .get_tinted(name, VISIBLE_TINT)
+                    .or_else(|| self.image_cache.get_tinted("ws/mixed_sec.png", VISIBLE_TINT))
+            })
+            .unwrap()
+            .into()
     }
 
     fn foreground(&self) -> Foreground {
-        Foreground::new()
+        match self.text {
+            // Keep the count badge in the corner, away from the icon
+            Some(ref text) if self.name.lock().unwrap().is_some() => {
+                Foreground::from(text.clone()).alignment(Alignment::RIGHT)
+            }
+            _ => Foreground::new(),
+        }
     }
 
     fn alignment(&self) -> Alignment {
         Alignment::LEFT
     }
 
+    // Hidden buttons take up no space
     fn width(&self) -> Width {
-        Width::new().fixed(60)
+        if self.name.lock().unwrap().is_some() {
+            Width::new().fixed(self.width)
+        } else {
+            Width::new().fixed(0)
+        }
     }
 }
+
+// Replace the value if it changed, returns `true` if it has been replaced
+fn update<T: PartialEq>(value: &Mutex<T>, new_value: T) -> bool {
+    let mut lock = value.lock().unwrap();
+    if *lock != new_value {
+        *lock = new_value;
+        true
+    } else {
+        false
+    }
+}
+
+// Fill in a command template
+//
+// The name can be the title of a window, so the values are quoted and
+// never expanded again.
+fn expand(template: &str, workspace: &str, name: &str, quote: fn(&str) -> String) -> String {
+    template
+        .split("{workspace}")
+        .map(|part| part.replace("{name}", &quote(name)))
+        .collect::<Vec<_>>()
+        .join(quote(workspace).as_str())
+}
+
+// Quote an argument of an i3 command, `;` and `,` would start another command otherwise
+fn i3_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Quote an argument of a shell command
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}